@@ -1,21 +1,20 @@
-use std::collections::{hash_map, HashMap, HashSet};
-
-#[derive(Debug, Clone, PartialEq)]
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{hash_map, BTreeMap, HashMap},
+    path::{Component, Path, PathBuf},
+};
+use unicode_normalization::UnicodeNormalization;
+
+// 文件夹的树, None 是文件.
+// 序列化时文件是 null, 文件夹是按名称排序的 map.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
 pub enum Cache {
     None,
     Map(HashMap<String, Box<Cache>>),
 }
 
 impl Cache {
-    // 嵌套的所有值都需要被查找.
-    pub fn contains(&self, source: &str) -> bool {
-        matches!(self, Cache::Map(map) if map.contains_key(source) || map.values().any(|cache| cache.contains(source)))
-    }
-
-    pub fn contains_set(&self, set: &HashSet<String>) -> bool {
-        set.iter().map(|source| self.contains(source)).any(|b| b)
-    }
-
     pub fn contains_key(&self, key: &str) -> bool {
         matches!(self, Cache::Map(map) if map.contains_key(key))
     }
@@ -40,7 +39,7 @@ impl Cache {
         None
     }
 
-    pub fn iter(&self) -> Iter {
+    pub fn iter(&self) -> Iter<'_> {
         match self {
             Cache::None => Iter {
                 inner: Item { opt: None },
@@ -53,7 +52,7 @@ impl Cache {
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut {
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
         match self {
             Cache::None => IterMut {
                 inner: ItemMut { opt: None },
@@ -72,6 +71,153 @@ impl Cache {
             Cache::Map(map) => Some(map.entry(key.to_owned())),
         }
     }
+
+    fn child(&self, name: &str) -> Option<&Cache> {
+        match self {
+            Cache::Map(map) => map.get(name).map(|x| x.as_ref()),
+            Cache::None => None,
+        }
+    }
+
+    // 按相对路径查找, 空路径是自身.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Cache> {
+        let mut cache = self;
+        for name in names(path.as_ref())? {
+            cache = cache.child(name)?;
+        }
+        Some(cache)
+    }
+
+    pub fn get_mut<P: AsRef<Path>>(&mut self, path: P) -> Option<&mut Cache> {
+        let mut cache = self;
+        for name in names(path.as_ref())? {
+            match cache {
+                Cache::Map(map) => cache = map.get_mut(name)?,
+                Cache::None => return None,
+            }
+        }
+        Some(cache)
+    }
+
+    // 去掉路径上的文件或文件夹, 返回去掉的部分.
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<Cache> {
+        let path = path.as_ref();
+        let name = names(path)?.pop()?;
+        match self.get_mut(path.parent()?)? {
+            Cache::Map(map) => map.remove(name).map(|x| *x),
+            Cache::None => None,
+        }
+    }
+
+    // 深度优先遍历所有子项, 返回相对路径, 同一层按名称排序.
+    pub fn walk(&self) -> Walk<'_> {
+        let mut walk = Walk { stack: Vec::new() };
+        walk.push(Path::new(""), self);
+        walk
+    }
+
+    // 合并另一棵树, 同名的文件夹递归合并, 其他情况使用 other 中的.
+    pub fn merge(&mut self, other: Cache) {
+        let Cache::Map(map) = self else {
+            *self = other;
+            return;
+        };
+        for (name, cache) in other {
+            match map.get_mut(&name) {
+                Some(old) if old.is_map() && cache.is_map() => old.merge(cache),
+                _ => {
+                    map.insert(name, Box::new(cache));
+                }
+            }
+        }
+    }
+
+    // 和 other 比较, other 中新增的和去掉的路径, 都按路径排序.
+    // 文件变成文件夹时既是去掉也是新增.
+    pub fn diff(&self, other: &Cache) -> CacheDiff {
+        let mut diff = CacheDiff::default();
+        diff_tree(self, other, Path::new(""), &mut diff);
+        diff.added.sort();
+        diff.removed.sort();
+        diff
+    }
+
+    pub fn is_map(&self) -> bool {
+        matches!(self, Cache::Map(_))
+    }
+}
+
+// 路径中的名称, 有 ".." 或者根时为 None.
+fn names(path: &Path) -> Option<Vec<&str>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(names)
+}
+
+fn diff_tree(old: &Cache, new: &Cache, parent: &Path, diff: &mut CacheDiff) {
+    let paths = |cache: &Cache, path: &Path| {
+        let mut paths = vec![path.to_path_buf()];
+        paths.extend(cache.walk().map(|(x, _)| path.join(x)));
+        paths
+    };
+    for (name, cache) in old {
+        let path = parent.join(name);
+        match new.child(name) {
+            Some(other) if other.is_map() == cache.is_map() => diff_tree(cache, other, &path, diff),
+            _ => diff.removed.extend(paths(cache, &path)),
+        }
+    }
+    for (name, cache) in new {
+        if old.child(name).is_none_or(|x| x.is_map() != cache.is_map()) {
+            diff.added.extend(paths(cache, &parent.join(name)));
+        }
+    }
+}
+
+// 两棵树的差别.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct CacheDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl Serialize for Cache {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cache::None => serializer.serialize_none(),
+            Cache::Map(map) => serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>()),
+        }
+    }
+}
+
+// 遍历的栈, 后进先出.
+pub struct Walk<'a> {
+    stack: Vec<(PathBuf, &'a Cache)>,
+}
+
+impl<'a> Walk<'a> {
+    fn push(&mut self, parent: &Path, cache: &'a Cache) {
+        let mut children: Vec<_> = cache.iter().collect();
+        children.sort_by(|a, b| b.0.cmp(a.0));
+        self.stack
+            .extend(children.into_iter().map(|(name, x)| (parent.join(name), x)));
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (PathBuf, &'a Cache);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, cache) = self.stack.pop()?;
+        self.push(&path, cache);
+        Some((path, cache))
+    }
 }
 
 // 实现 From 特性.
@@ -191,6 +337,74 @@ impl IntoIterator for Cache {
     }
 }
 
+// 文件名到所在位置的反向索引, 和 Cache 一起建立, 查找时不需要遍历整棵树.
+// owner 是 Cache 所属的动漫, 路径相对于它的根.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameIndex {
+    names: HashMap<String, Vec<(String, PathBuf)>>,
+    keys: HashMap<String, Vec<String>>, // 每个 owner 添加的名称, 去掉时不需要遍历整个索引.
+}
+
+impl NameIndex {
+    // 统一全角半角和大小写.
+    pub fn normalize(name: &str) -> String {
+        name.nfkc().flat_map(char::to_lowercase).collect()
+    }
+
+    // 添加一棵树中的所有文件和文件夹, 同一个 owner 已有的先去掉.
+    pub fn insert(&mut self, owner: &str, cache: &Cache) {
+        self.remove(owner);
+        self.insert_tree(owner, cache, Path::new(""));
+    }
+
+    fn insert_tree(&mut self, owner: &str, cache: &Cache, parent: &Path) {
+        for (name, child) in cache {
+            let path = parent.join(name);
+            let key = Self::normalize(name);
+            self.names
+                .entry(key.clone())
+                .or_default()
+                .push((owner.to_string(), path.clone()));
+            self.keys.entry(owner.to_string()).or_default().push(key);
+            self.insert_tree(owner, child, &path);
+        }
+    }
+
+    pub fn remove(&mut self, owner: &str) {
+        for key in self.keys.remove(owner).unwrap_or_default() {
+            let Some(locations) = self.names.get_mut(&key) else {
+                continue;
+            };
+            locations.retain(|(o, _)| o != owner);
+            if locations.is_empty() {
+                self.names.remove(&key);
+            }
+        }
+    }
+
+    // 名称所在的位置.
+    pub fn get(&self, name: &str) -> &[(String, PathBuf)] {
+        self.names
+            .get(&Self::normalize(name))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    // 包含这个名称的 owner, 不重复.
+    pub fn owners(&self, name: &str) -> Vec<&str> {
+        let mut owners: Vec<&str> = Vec::new();
+        for (owner, _) in self.get(name) {
+            if !owners.contains(&owner.as_str()) {
+                owners.push(owner);
+            }
+        }
+        owners
+    }
+
+    pub fn contains(&self, owner: &str, name: &str) -> bool {
+        self.get(name).iter().any(|(o, _)| o == owner)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,29 +421,16 @@ mod tests {
     }
 
     #[test]
-    fn contains() {
+    fn insert() {
         let mut cache = Cache::default();
-        assert!(!cache.contains("a"));
+        assert!(!cache.contains_key("a"));
 
         cache.insert_none("a");
         cache.insert("b", Cache::from([("b.0", Cache::None)]));
-        assert!(cache.contains("a"));
-        assert!(cache.contains("b"));
-        assert!(cache.contains("b.0"));
-        assert!(!cache.contains("c"));
-    }
-
-    #[test]
-    fn contains_set() {
-        let cache = Cache::from([
-            ("a", Cache::None),
-            ("b", Cache::from([("b.0", Cache::None)])),
-        ]);
-
-        assert!(cache.contains_set(&HashSet::from(["a".to_string()])));
-        assert!(cache.contains_set(&HashSet::from(["b.0".to_string()])));
-        assert!(!cache.contains_set(&HashSet::from(["c".to_string()])));
-        assert!(cache.contains_set(&HashSet::from(["c".to_string(), "b.0".to_string()])));
+        assert!(cache.contains_key("a"));
+        assert!(cache.contains_key("b"));
+        assert!(!cache.contains_key("b.0"));
+        assert_eq!(cache.get("b/b.0"), Some(&Cache::None));
     }
 
     #[test]
@@ -247,6 +448,92 @@ mod tests {
         );
     }
 
+    #[test]
+    fn path() {
+        let mut cache = get_cache();
+        assert_eq!(cache.get("c/c.1"), Some(&Cache::None));
+        assert_eq!(cache.get(""), Some(&get_cache()));
+        assert_eq!(cache.get("a/b"), None);
+        assert_eq!(cache.get("../a"), None);
+        let paths: Vec<_> = cache.walk().map(|(x, _)| x).collect();
+        assert_eq!(paths, ["a", "b", "c", "c/c.0", "c/c.1"].map(PathBuf::from));
+
+        assert_eq!(cache.remove("c/c.0"), Some(Cache::None));
+        assert_eq!(cache.remove("c/c.0"), None);
+        assert_eq!(cache.remove("a"), Some(Cache::None));
+        assert_eq!(
+            cache,
+            Cache::from([
+                ("b", Cache::None),
+                ("c", Cache::from([("c.1", Cache::None)]))
+            ])
+        );
+
+        cache.merge(Cache::from([
+            ("b", Cache::from([("b.0", Cache::None)])),
+            ("c", Cache::from([("c.2", Cache::None)])),
+        ]));
+        let paths: Vec<_> = cache.walk().map(|(x, _)| x).collect();
+        assert_eq!(
+            paths,
+            ["b", "b/b.0", "c", "c/c.1", "c/c.2"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let old = get_cache();
+        let mut new = get_cache();
+        new.remove("c/c.0");
+        new.remove("b");
+        new.insert("b", Cache::from([("b.0", Cache::None)]));
+        new.insert("d", Cache::from([("d.0", Cache::None)]));
+        assert_eq!(
+            old.diff(&new),
+            CacheDiff {
+                added: ["b", "b/b.0", "d", "d/d.0"].map(PathBuf::from).to_vec(),
+                removed: ["b", "c/c.0"].map(PathBuf::from).to_vec(),
+            }
+        );
+        assert_eq!(new.diff(&new), CacheDiff::default());
+    }
+
+    #[test]
+    fn serde() {
+        let mut cache = get_cache();
+        cache.insert("empty", Cache::default());
+        let json = serde_json::to_string(&cache).unwrap();
+        assert_eq!(
+            json,
+            r#"{"a":null,"b":null,"c":{"c.0":null,"c.1":null},"empty":{}}"#
+        );
+        assert_eq!(serde_json::from_str::<Cache>(&json).unwrap(), cache);
+        let yaml = serde_yaml::to_string(&cache).unwrap();
+        assert_eq!(serde_yaml::from_str::<Cache>(&yaml).unwrap(), cache);
+    }
+
+    #[test]
+    fn name_index() {
+        let mut index = NameIndex::default();
+        index.insert("AIR", &get_cache());
+        index.insert("Kanon", &Cache::from([("C", Cache::None)]));
+        assert_eq!(
+            index.get("c.1"),
+            [("AIR".to_string(), PathBuf::from("c/c.1"))]
+        );
+        assert_eq!(index.owners("ｃ"), ["AIR", "Kanon"]);
+        assert!(index.contains("Kanon", "c"));
+        assert!(!index.contains("Kanon", "a"));
+
+        index.insert("AIR", &Cache::from([("d", Cache::None)]));
+        assert!(index.get("a").is_empty());
+        assert_eq!(index.owners("c"), ["Kanon"]);
+        index.remove("Kanon");
+        assert!(index.get("c").is_empty());
+        index.remove("AIR");
+        assert_eq!(index, NameIndex::default());
+    }
+
     #[test]
     fn iter_mut() {
         let mut cache = get_cache();
@@ -1,109 +1,807 @@
-use std::fmt;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    descend::{DescendConfig, DescendRules},
+    linker::LinkMode,
+    media::MediaRules,
+    rename::Template,
+    source_anime_map::Layout,
+};
+
+const DEFAULT_MAPFILE: &str = ".data/data.yaml";
+const DEFAULT_IGNORE: [&str; 4] = ["*.parts", "*.part", "*.!qB", "*.torrent"];
+const DEFAULT_BACKUPS: usize = 10;
+const DEFAULT_MATCH_THRESHOLD: f64 = 0.8;
+const DEFAULT_MIN_CONFIDENCE: f64 = 0.5;
+const DEFAULT_JOBS: usize = 4;
 
 #[derive(Debug)]
 pub struct Config {
     pub action: Action,
     pub mapfile_path: String,
-    pub source_path: String,
-    pub anime_path: String,
+    pub roots: Vec<Root>,            // 源文件夹和动漫文件夹的组合, 可以有多个.
+    pub link_modes: Vec<LinkMode>,   // 全局的链接回退链.
+    pub profile: Option<String>,     // 使用的配置档案.
+    pub media: MediaRules,           // 媒体文件的分类.
+    pub descend: DescendRules,       // 建立动漫索引时进入哪些子文件夹.
+    pub ignore: Vec<String>,         // 源文件夹中忽略的文件, gitignore 语法.
+    pub dry_run: bool,               // 只读运行, 不写任何文件.
+    pub format: OutputFormat,        // 计划的输出格式.
+    pub backups: usize,              // map 文件保留的备份数量.
+    pub lock_wait: Option<Duration>, // 等待 map 文件锁的时间, None 为一直等待.
+    pub rename: Option<Template>,    // 链接后重命名文件的模板, None 为不重命名.
+    pub match_threshold: f64,        // 按标题匹配动漫时的最低相似度.
+    pub min_confidence: f64,         // 自动匹配的最低置信度, 低于它的需要人工确认.
+    pub rebuild_index: bool,         // 忽略保存的动漫索引, 重新读取所有动漫文件夹.
+    pub jobs: usize,                 // 同时读取文件夹的线程数量.
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+// 命令行参数, 解析后转换成 Config.
+// 优先级: 命令行 > 环境变量 > 配置文件 > 默认值.
+#[derive(Parser, Debug)]
+#[command(
+    name = "anime_reflink",
+    version,
+    about = "Link downloaded anime into the library."
+)]
+struct Cli {
+    #[command(subcommand)]
+    action: Action,
+
+    /// Config file, defaults to $XDG_CONFIG_HOME/anime_reflink/config.yaml.
+    #[arg(short, long, global = true, env = "ANIME_REFLINK_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Profile in the config file.
+    #[arg(short, long, global = true, env = "ANIME_REFLINK_PROFILE")]
+    profile: Option<String>,
+
+    /// Map file that stores the source -> anime mappings [default: .data/data.yaml].
+    #[arg(short, long, global = true, env = "ANIME_REFLINK_MAP_FILE")]
+    map_file: Option<String>,
+
+    /// Root of the downloaded sources, repeat it with --anime-root for more pairs.
+    #[arg(short, long, global = true, env = "ANIME_REFLINK_SOURCE_ROOT")]
+    source_root: Vec<String>,
+
+    /// Root of the anime library, paired with --source-root in order.
+    #[arg(short, long, global = true, env = "ANIME_REFLINK_ANIME_ROOT")]
+    anime_root: Vec<String>,
+
+    /// Link modes tried in order, e.g. "reflink,hardlink" [default: reflink].
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "ANIME_REFLINK_LINK_MODE",
+        value_delimiter = ',',
+        value_parser = LinkMode::from_str
+    )]
+    link_mode: Option<Vec<LinkMode>>,
+
+    /// Print the plan instead of linking or writing anything.
+    #[arg(short = 'n', long, global = true)]
+    dry_run: bool,
+
+    /// Seconds to wait for another run holding the map file, 0 fails at once [default: forever].
+    #[arg(long, global = true, env = "ANIME_REFLINK_LOCK_WAIT")]
+    lock_wait: Option<u64>,
+
+    /// Number of map file backups to keep, 0 disables backups [default: 10].
+    #[arg(long, global = true, env = "ANIME_REFLINK_BACKUPS")]
+    backups: Option<usize>,
+
+    /// Rename linked files by a template, e.g. "{title} - S{season:02}E{episode:02}{ext}".
+    /// Passing an empty value disables it.
+    #[arg(long, global = true, env = "ANIME_REFLINK_RENAME")]
+    rename: Option<String>,
+
+    /// Lowest title similarity, from 0 to 1, to match a source to an anime by name [default: 0.8].
+    #[arg(long, global = true, env = "ANIME_REFLINK_MATCH_THRESHOLD")]
+    match_threshold: Option<f64>,
+
+    /// Lowest confidence, from 0 to 1, to map a source without review [default: 0.5].
+    #[arg(long, global = true, env = "ANIME_REFLINK_MIN_CONFIDENCE")]
+    min_confidence: Option<f64>,
+
+    /// Ignore the saved anime index and read every anime folder again.
+    #[arg(long, global = true)]
+    rebuild_index: bool,
+
+    /// Number of folders read at the same time when scanning [default: 4].
+    #[arg(short, long, global = true, env = "ANIME_REFLINK_JOBS")]
+    jobs: Option<usize>,
+
+    /// Output format of the plan.
+    #[arg(short, long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
 }
 
 impl Config {
-    pub fn new(args: impl Iterator<Item = String>) -> Config {
-        let mut args = args.skip(1);
-        let action: Action = match args.next() {
-            Some(x) => Action::from(x.as_str()),
-            None => Action::Test,
-        };
-        let mapfile_path = match args.next() {
-            Some(arg) => arg,
-            None => ".data/data.yaml".to_string(),
+    // 参数错误时返回 clap 的错误, 由调用方决定输出并退出.
+    pub fn new(args: impl Iterator<Item = String>) -> Result<Config, clap::Error> {
+        let cli = Cli::try_parse_from(args)?;
+        let file = match &cli.config {
+            Some(path) => ConfigFile::from_path(path),
+            None => match ConfigFile::default_path() {
+                Some(path) if path.is_file() => ConfigFile::from_path(&path),
+                _ => Ok(ConfigFile::default()),
+            },
+        }
+        .map_err(|e| Cli::command().error(ErrorKind::Io, e))?;
+        let profile_name = cli.profile.clone().or(file.default_profile.clone());
+        let profile = file
+            .profile(profile_name.as_deref())
+            .map_err(|e| Cli::command().error(ErrorKind::InvalidValue, e))?;
+
+        let roots = Root::resolve(cli.source_root, cli.anime_root, &profile)
+            .map_err(|e| Cli::command().error(ErrorKind::WrongNumberOfValues, e))?;
+        if cli.action.needs_roots() && roots.is_empty() {
+            let msg =
+                "--source-root and --anime-root are required, set them by flags, env or a profile";
+            return Err(Cli::command().error(ErrorKind::MissingRequiredArgument, msg));
+        }
+        let ignore = profile
+            .ignore
+            .unwrap_or(DEFAULT_IGNORE.map(String::from).to_vec());
+
+        // video_extensions 是 media.video 的简写.
+        let mut media = profile.media.unwrap_or_default();
+        if let Some(video) = profile.video_extensions {
+            media.video = video;
+        }
+
+        let descend = DescendRules::new(&profile.descend.unwrap_or_default())
+            .map_err(|e| Cli::command().error(ErrorKind::InvalidValue, e))?;
+
+        let rename = cli
+            .rename
+            .or(profile.rename)
+            .filter(|x| !x.is_empty())
+            .map(|x| Template::new(&x))
+            .transpose()
+            .map_err(|e| Cli::command().error(ErrorKind::InvalidValue, e))?;
+
+        let match_threshold = cli
+            .match_threshold
+            .or(profile.match_threshold)
+            .unwrap_or(DEFAULT_MATCH_THRESHOLD);
+        let min_confidence = cli
+            .min_confidence
+            .or(profile.min_confidence)
+            .unwrap_or(DEFAULT_MIN_CONFIDENCE);
+        for (name, value) in [
+            ("match threshold", match_threshold),
+            ("min confidence", min_confidence),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                let msg = format!("{} {} is not in 0..=1", name, value);
+                return Err(Cli::command().error(ErrorKind::InvalidValue, msg));
+            }
+        }
+
+        let jobs = cli.jobs.or(profile.jobs).unwrap_or(DEFAULT_JOBS);
+        if jobs == 0 {
+            return Err(Cli::command().error(ErrorKind::InvalidValue, "jobs must be at least 1"));
+        }
+
+        // plan 总是只读的.
+        let dry_run = cli.dry_run || matches!(cli.action, Action::Plan(_));
+        Ok(Config {
+            action: cli.action,
+            mapfile_path: cli
+                .map_file
+                .or(profile.map_file)
+                .unwrap_or(DEFAULT_MAPFILE.to_string()),
+            roots,
+            link_modes: cli
+                .link_mode
+                .or(profile.link_mode)
+                .filter(|x| !x.is_empty())
+                .unwrap_or(vec![LinkMode::Reflink]),
+            profile: profile_name,
+            media,
+            descend,
+            ignore,
+            dry_run,
+            format: cli.format,
+            backups: cli.backups.or(profile.backups).unwrap_or(DEFAULT_BACKUPS),
+            lock_wait: cli.lock_wait.or(profile.lock_wait).map(Duration::from_secs),
+            rename,
+            match_threshold,
+            min_confidence,
+            rebuild_index: cli.rebuild_index,
+            jobs,
+        })
+    }
+}
+
+// 一组源文件夹和动漫文件夹.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Root {
+    #[serde(default)]
+    pub name: String, // 记录在 map 中的名称, 默认为源文件夹的名称.
+    pub source: String,
+    pub anime: String,
+}
+
+impl Root {
+    // 名称不包含挂载的位置, 计划文件可以在挂载位置不同的机器上执行.
+    pub fn new(source: String, anime: String) -> Root {
+        let name = Path::new(&source)
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or(&source)
+            .to_string();
+        Root {
+            name,
+            source,
+            anime,
+        }
+    }
+
+    // 名称重复时 map 无法区分属于哪一组, 需要在档案中指定.
+    fn check_names(roots: Vec<Root>) -> Result<Vec<Root>, String> {
+        for (i, root) in roots.iter().enumerate() {
+            if roots[..i].iter().any(|x| x.name == root.name) {
+                return Err(format!(
+                    "duplicate root name {}, set a name for {} in the profile",
+                    root.name, root.source
+                ));
+            }
+        }
+        Ok(roots)
+    }
+
+    // 命令行中的文件夹按顺序配对, 逐项覆盖档案中的单组文件夹.
+    // 命令行没有文件夹时才使用档案中的多组文件夹.
+    fn resolve(
+        source_roots: Vec<String>,
+        anime_roots: Vec<String>,
+        profile: &Profile,
+    ) -> Result<Vec<Root>, String> {
+        if source_roots.is_empty() && anime_roots.is_empty() {
+            if let Some(roots) = &profile.roots {
+                return Root::check_names(
+                    roots
+                        .iter()
+                        .map(|x| match x.name.is_empty() {
+                            true => Root::new(x.source.clone(), x.anime.clone()),
+                            false => x.clone(),
+                        })
+                        .collect(),
+                );
+            }
+        }
+        let or_profile = |roots: Vec<String>, root: &Option<String>| match roots.is_empty() {
+            true => root.iter().cloned().collect(),
+            false => roots,
         };
-        let source_path = args.next().unwrap_or("X:\\SOURCE".to_string());
-        let anime_path = args.next().unwrap_or("X:\\ANIME".to_string());
-
-        Config {
-            action,
-            mapfile_path,
-            source_path,
-            anime_path,
+        let source_roots: Vec<_> = or_profile(source_roots, &profile.source_root);
+        let anime_roots: Vec<_> = or_profile(anime_roots, &profile.anime_root);
+        if source_roots.len() != anime_roots.len() {
+            return Err(format!(
+                "{} source roots but {} anime roots",
+                source_roots.len(),
+                anime_roots.len()
+            ));
         }
+        Root::check_names(
+            source_roots
+                .into_iter()
+                .zip(anime_roots)
+                .map(|(source, anime)| Root::new(source, anime))
+                .collect(),
+        )
     }
 }
 
-#[derive(Debug)]
+// 配置文件, 可以定义多个档案.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+// 一组源文件夹和动漫文件夹的配置, 未设置的字段使用默认值.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub source_root: Option<String>,
+    pub anime_root: Option<String>,
+    pub roots: Option<Vec<Root>>, // 多组文件夹, 和 source_root/anime_root 二选一.
+    pub map_file: Option<String>,
+    pub link_mode: Option<Vec<LinkMode>>,
+    pub video_extensions: Option<Vec<String>>,
+    pub media: Option<MediaRules>,
+    pub descend: Option<DescendConfig>,
+    pub ignore: Option<Vec<String>>,
+    pub backups: Option<usize>,
+    pub lock_wait: Option<u64>, // 秒.
+    pub rename: Option<String>,
+    pub match_threshold: Option<f64>,
+    pub min_confidence: Option<f64>,
+    pub jobs: Option<usize>,
+}
+
+impl ConfigFile {
+    pub fn from_path(path: &Path) -> Result<ConfigFile, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("read config {} failed: {}", path.display(), e))?;
+        let file = serde_yaml::from_str(&content)
+            .map_err(|e| format!("parse config {} failed: {}", path.display(), e))?;
+        Ok(file)
+    }
+
+    // $XDG_CONFIG_HOME/anime_reflink/config.yaml, 未设置时使用 ~/.config.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+            .or(env::var_os("HOME").map(|x| Path::new(&x).join(".config")))?;
+        Some(dir.join("anime_reflink").join("config.yaml"))
+    }
+
+    // 没有指定档案时使用空档案.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, String> {
+        let Some(name) = name else {
+            return Ok(Profile::default());
+        };
+        self.profiles.get(name).cloned().ok_or_else(|| {
+            let names: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+            format!(
+                "profile not found: {}, available: [{}]",
+                name,
+                names.join(", ")
+            )
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
 pub enum Action {
-    Test,
+    /// Record new sources and animes without matching.
+    Scan,
+    /// Show what link would do, without writing anything.
+    Plan(PlanArgs),
+    /// Link the tasks of a reviewed plan file.
+    Apply(ApplyArgs),
+    /// Match sources to animes and link them.
+    Link,
+    /// Rescan the file types of recorded sources.
     Renew,
-    Reflink,
+    /// Show the recorded mappings.
+    Status,
+    /// Edit a recorded mapping by hand.
+    Edit(EditArgs),
+    /// List the backups of the map file, or roll back to one.
+    Restore(RestoreArgs),
+    /// Rename the linked files again by the template, or undo the renaming.
+    Rename(RenameArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct PlanArgs {
+    /// Save the tasks to a plan file for apply.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ApplyArgs {
+    /// Plan file written by plan --output.
+    pub plan: PathBuf,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RestoreArgs {
+    /// Backup to restore, by its number in the list or its file name.
+    pub backup: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RenameArgs {
+    /// Only rename this source, nested sources are written as "Collection/Series/Season".
+    pub source: Option<String>,
+
+    /// Root name of the source, needed when several roots have the same source.
+    #[arg(long)]
+    pub root: Option<String>,
+
+    /// Restore the original names.
+    #[arg(long)]
+    pub undo: bool,
+}
+
+// 手动修改 map 的参数.
+#[derive(Args, Debug, Clone)]
+pub struct EditArgs {
+    /// Source name, nested sources are written as "Collection/Series/Season".
+    pub source: String,
+
+    /// Root name of the source, needed when several roots have the same source.
+    #[arg(long)]
+    pub root: Option<String>,
+
+    /// New anime name.
+    #[arg(long)]
+    pub anime: Option<String>,
+
+    /// Whether the map is active.
+    #[arg(long)]
+    pub active: Option<bool>,
+
+    /// Link modes of this map, passing no value clears it.
+    #[arg(long, value_delimiter = ',', num_args = 0.., value_parser = LinkMode::from_str)]
+    pub link_mode: Option<Vec<LinkMode>>,
+
+    /// Sub-path under the anime folder, passing an empty value clears it.
+    #[arg(long)]
+    pub sub_path: Option<String>,
+
+    /// How the source is placed: nested (as is), flatten or season.
+    #[arg(long, value_parser = Layout::from_str)]
+    pub layout: Option<Layout>,
+
+    /// Season number of the season layout, detected from the source name if not set.
+    #[arg(long)]
+    pub season: Option<u32>,
+
+    /// Rename template of this map, passing an empty value disables renaming for it.
+    #[arg(long)]
+    pub rename: Option<String>,
+
+    /// File inside the source to map on its own, relative to the source.
+    #[arg(long)]
+    pub file: Option<String>,
+
+    /// Destination of the file, relative to the anime folder of the root.
+    /// Passing an empty value links it with the folder again.
+    #[arg(long, requires = "file")]
+    pub destination: Option<String>,
+
+    /// Whether the file is linked.
+    #[arg(long, requires = "file")]
+    pub include: Option<bool>,
+}
+
+impl Action {
+    // 需要扫描文件夹的动作.
+    pub fn needs_roots(&self) -> bool {
+        matches!(
+            self,
+            Action::Scan
+                | Action::Plan(_)
+                | Action::Apply(_)
+                | Action::Link
+                | Action::Renew
+                | Action::Rename(_)
+        )
+    }
 }
 
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Action::*; // 非常方便的通配符用法，将枚举名称暂时放入方法上下文中.
         match self {
-            Test => write!(f, "test"),
+            Scan => write!(f, "scan"),
+            Plan(_) => write!(f, "plan"),
+            Apply(_) => write!(f, "apply"),
+            Link => write!(f, "link"),
             Renew => write!(f, "renew"),
-            Reflink => write!(f, "reflink"),
+            Status => write!(f, "status"),
+            Edit(_) => write!(f, "edit"),
+            Restore(_) => write!(f, "restore"),
+            Rename(_) => write!(f, "rename"),
         }
     }
 }
 
-impl From<&str> for Action {
-    fn from(s: &str) -> Self {
-        match s {
-            "test" => Action::Test,
-            "renew" => Action::Renew,
-            "reflink" => Action::Reflink,
-            _ => Action::Test,
+// 测试不读取开发者的配置文件和 ANIME_REFLINK_* 环境变量,
+// 配置文件夹指向一个不存在的临时文件夹. 所有调用 Config::new 的测试都要先调用它.
+#[cfg(test)]
+pub(crate) fn isolate_env() {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| {
+        for (key, _) in env::vars_os() {
+            if key.to_string_lossy().starts_with("ANIME_REFLINK_") {
+                env::remove_var(key);
+            }
         }
-    }
+        let dir = env::temp_dir().join(format!("anime_reflink-test-{}", std::process::id()));
+        env::set_var("XDG_CONFIG_HOME", dir);
+    });
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::media::MediaKind;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        isolate_env();
+        let mut v = vec!["anime_reflink".to_string()];
+        v.extend(args.iter().map(|x| x.to_string()));
+        v.into_iter()
+    }
 
     #[test]
     fn config() {
-        let args: Vec<String> = vec![];
-        let config = Config::new(args.into_iter());
-        assert_eq!(config.action.to_string(), Action::Test.to_string());
+        let config = Config::new(args(&["status"])).unwrap();
+        assert_eq!(config.action.to_string(), "status");
         assert_eq!(
             config.mapfile_path, ".data/data.yaml",
             "mapfile_path {}",
             config.mapfile_path
         );
-        assert_eq!(config.source_path, "X:\\SOURCE");
-        assert_eq!(config.anime_path, "X:\\ANIME");
-
-        let args = vec![
-            "".to_string(),
-            "renew".to_string(),
-            ".data/data.1.yaml".to_string(),
-            "./SOURCE".to_string(),
-            "./ANIME".to_string(),
-        ];
-        let config = Config::new(args.into_iter());
-        assert_eq!(config.action.to_string(), Action::Renew.to_string());
+        assert_eq!(config.link_modes, vec![LinkMode::Reflink]);
+        assert!(config.media.is_video("a.m2ts"));
+        assert!(config.ignore.contains(&"*.parts".to_string()));
+        assert!(!config.dry_run);
+        assert_eq!(config.format, OutputFormat::Human);
+        assert_eq!(config.backups, 10);
+        assert_eq!(config.lock_wait, None);
+        assert_eq!(config.match_threshold, 0.8);
+        assert_eq!(config.min_confidence, 0.5);
+        assert!(!config.rebuild_index);
+        assert_eq!(config.jobs, 4);
+
+        // 全局参数在子命令前后都可以.
+        let config = Config::new(args(&[
+            "--map-file",
+            ".data/data.1.yaml",
+            "renew",
+            "-s",
+            "./SOURCE",
+            "--anime-root",
+            "./ANIME",
+            "--link-mode",
+            "reflink,hardlink",
+        ]))
+        .unwrap();
+        assert_eq!(config.action.to_string(), "renew");
+        assert!(!config.dry_run);
         assert_eq!(
             config.mapfile_path, ".data/data.1.yaml",
             "mapfile_path {}",
             config.mapfile_path
         );
-        assert_eq!(config.source_path, "./SOURCE");
-        assert_eq!(config.anime_path, "./ANIME");
+        assert_eq!(
+            config.roots,
+            [Root::new("./SOURCE".to_string(), "./ANIME".to_string())]
+        );
+        assert_eq!(
+            config.link_modes,
+            vec![LinkMode::Reflink, LinkMode::Hardlink]
+        );
+
+        let config = Config::new(args(&[
+            "edit", "a/b", "--anime", "AIR", "--active", "false",
+        ]))
+        .unwrap();
+        let Action::Edit(edit) = config.action else {
+            panic!("")
+        };
+        assert_eq!(edit.source, "a/b");
+        assert_eq!(edit.anime.as_deref(), Some("AIR"));
+        assert_eq!(edit.active, Some(false));
+    }
+
+    #[test]
+    fn config_error() {
+        // 没有子命令, 拼错的子命令和错误的链接方式都要报错.
+        assert!(Config::new(args(&[])).is_err());
+        assert!(Config::new(args(&["reflnk"])).is_err());
+        assert!(Config::new(args(&["link", "--link-mode", "reflnk"])).is_err());
+        assert!(Config::new(args(&["plan", "./SOURCE"])).is_err());
+        // 扫描类的动作必须有文件夹.
+        assert!(Config::new(args(&["plan", "-s", "./SOURCE"])).is_err());
+        assert!(Config::new(args(&["status", "--config", "./not_exist.yaml"])).is_err());
+    }
+
+    // 所有档案都使用的配置文件.
+    fn config_file(tep_dir: &tempfile::TempDir) -> String {
+        let path = tep_dir.path().join("config.yaml");
+        fs::write(
+            &path,
+            r#"
+default_profile: tv
+profiles:
+  tv:
+    source_root: /dl/tv
+    anime_root: /lib/anime
+    map_file: /lib/tv.yaml
+    link_mode: [reflink, hardlink]
+  movie:
+    source_root: /dl/movie
+    anime_root: /lib/movie
+    video_extensions: [.mkv, .m2ts]
+    ignore: ["*.torrent"]
+    media:
+      link: [video, subtitle]
+    descend:
+      include: ["re:^S\\d+$"]
+    backups: 3
+    lock_wait: 30
+    match_threshold: 0.9
+    min_confidence: 0.6
+    jobs: 8
+    rename: "{title} - S{season:02}E{episode:02}{ext}"
+  all:
+    roots:
+      - name: tv
+        source: /dl/tv
+        anime: /lib/anime
+      - source: /dl/movie
+        anime: /lib/movie
+"#,
+        )
+        .unwrap();
+        path.to_str().unwrap().to_string()
+    }
 
-        let args = vec!["".to_string(), "reflink".to_string()];
-        let config = Config::new(args.into_iter());
-        assert_eq!(config.action.to_string(), Action::Reflink.to_string());
+    #[test]
+    fn default_profile() {
+        let tep_dir = tempfile::tempdir_in("./").unwrap();
+        let path = &config_file(&tep_dir);
+        let config = Config::new(args(&[
+            "plan",
+            "-c",
+            path,
+            "--format",
+            "json",
+            "-o",
+            "plan.yaml",
+        ]))
+        .unwrap();
+        assert!(config.dry_run);
+        let Action::Plan(plan) = &config.action else {
+            panic!("")
+        };
+        assert_eq!(plan.output, Some(PathBuf::from("plan.yaml")));
+        assert_eq!(config.format, OutputFormat::Json);
+        assert_eq!(config.profile.as_deref(), Some("tv"));
+        assert_eq!(config.roots[0].source, "/dl/tv");
+        assert_eq!(config.mapfile_path, "/lib/tv.yaml");
+        assert_eq!(
+            config.link_modes,
+            vec![LinkMode::Reflink, LinkMode::Hardlink]
+        );
+        assert!(Config::new(args(&["plan", "-c", path, "-p", "music"])).is_err());
     }
 
     #[test]
-    fn action_from() {
-        assert!(matches!(Action::from("test"), Action::Test));
-        assert!(matches!(Action::from("renew"), Action::Renew));
-        assert!(matches!(Action::from("reflink"), Action::Reflink));
-        assert!(matches!(Action::from("nottest"), Action::Test));
+    fn profile_override() {
+        // 命令行覆盖档案.
+        let tep_dir = tempfile::tempdir_in("./").unwrap();
+        let path = &config_file(&tep_dir);
+        let config = Config::new(args(&[
+            "plan", "-c", path, "-p", "movie", "-a", "./ANIME", "-l", "copy",
+        ]))
+        .unwrap();
+        assert_eq!(config.roots[0].source, "/dl/movie");
+        assert_eq!(config.roots[0].anime, "./ANIME");
+        assert_eq!(config.mapfile_path, ".data/data.yaml");
+        assert_eq!(config.link_modes, vec![LinkMode::Copy]);
+        assert_eq!(config.ignore, ["*.torrent"]);
+        assert_eq!(config.backups, 3);
+        assert_eq!(config.lock_wait, Some(Duration::from_secs(30)));
+        let config =
+            Config::new(args(&["plan", "-c", path, "-p", "movie", "--backups", "0"])).unwrap();
+        assert_eq!(config.backups, 0);
+
+        let config = Config::new(args(&["plan", "-c", path, "-p", "all"])).unwrap();
+        assert_eq!(config.roots.len(), 2);
+        assert_eq!(config.roots[0].name, "tv");
+        assert_eq!(config.roots[1].name, "movie");
+    }
+
+    #[test]
+    fn profile_media() {
+        let tep_dir = tempfile::tempdir_in("./").unwrap();
+        let path = &config_file(&tep_dir);
+        let config = Config::new(args(&["plan", "-c", path, "-p", "movie"])).unwrap();
+        assert_eq!(config.media.video, [".mkv", ".m2ts"]);
+        assert_eq!(config.media.link, [MediaKind::Video, MediaKind::Subtitle]);
+        assert_eq!(config.media.font, MediaRules::default().font);
+        assert!(config.descend.should_descend("S2", 1));
+        assert!(!config.descend.should_descend("Season 01", 1));
+    }
+
+    #[test]
+    fn profile_rename() {
+        let tep_dir = tempfile::tempdir_in("./").unwrap();
+        let path = &config_file(&tep_dir);
+        let config = Config::new(args(&["plan", "-c", path, "-p", "movie"])).unwrap();
+        let rename = config.rename.as_ref().map(Template::as_str);
+        assert_eq!(rename, Some("{title} - S{season:02}E{episode:02}{ext}"));
+        let disabled = Config::new(args(&["plan", "-c", path, "-p", "movie", "--rename", ""]));
+        assert!(disabled.unwrap().rename.is_none());
+        let invalid = Config::new(args(&["plan", "-c", path, "--rename", "{ep}"]));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn profile_matching() {
+        let tep_dir = tempfile::tempdir_in("./").unwrap();
+        let path = &config_file(&tep_dir);
+        let config = Config::new(args(&["plan", "-c", path, "-p", "movie"])).unwrap();
+        assert_eq!(config.match_threshold, 0.9);
+        assert_eq!(config.min_confidence, 0.6);
+        let threshold = Config::new(args(&["plan", "-c", path, "--match-threshold", "1.5"]));
+        assert!(threshold.is_err());
+        let confidence = Config::new(args(&["plan", "-c", path, "--min-confidence", "1.5"]));
+        assert!(confidence.is_err());
+    }
+
+    #[test]
+    fn profile_jobs() {
+        let tep_dir = tempfile::tempdir_in("./").unwrap();
+        let path = &config_file(&tep_dir);
+        let config = Config::new(args(&["plan", "-c", path, "-p", "movie"])).unwrap();
+        assert_eq!(config.jobs, 8);
+        let config = Config::new(args(&["plan", "-c", path, "-p", "movie", "-j", "2"])).unwrap();
+        assert_eq!(config.jobs, 2);
+        assert!(Config::new(args(&["plan", "-c", path, "-j", "0"])).is_err());
+    }
+
+    #[test]
+    fn roots() {
+        let config = Config::new(args(&[
+            "scan",
+            "-s",
+            "./TV",
+            "-a",
+            "./ANIME",
+            "-s",
+            "./MOVIE",
+            "-a",
+            "./MOVIE_LIB",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.roots,
+            [
+                Root::new("./TV".to_string(), "./ANIME".to_string()),
+                Root::new("./MOVIE".to_string(), "./MOVIE_LIB".to_string()),
+            ]
+        );
+        assert_eq!(config.roots[0].name, "TV");
+        assert!(Config::new(args(&[
+            "scan", "-s", "./TV", "-a", "./ANIME", "-s", "./MOVIE"
+        ]))
+        .is_err());
+        // 挂载在不同位置的同名文件夹需要在档案中指定名称.
+        assert!(Config::new(args(&[
+            "scan",
+            "-s",
+            "/a/TV",
+            "-a",
+            "./ANIME",
+            "-s",
+            "/b/TV",
+            "-a",
+            "./MOVIE_LIB"
+        ]))
+        .is_err());
     }
 }
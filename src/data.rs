@@ -1,23 +1,54 @@
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::{
+    cell::RefCell,
     collections::{HashMap, HashSet},
     error::Error,
-    fs::{self, DirEntry, File},
+    fs::{self, DirEntry},
+    io,
     path::{Path, PathBuf},
-    process::Command,
+    slice,
 };
 
 use crate::{
-    cache::Cache,
-    config::{Action, Config},
-    source_anime_map::{FileType, SourceAnimeMap, Value},
+    cache::{Cache, NameIndex},
+    config::{Action, Config, EditArgs, RenameArgs, Root},
+    fingerprint::FingerprintCache,
+    ignore_rules::{IgnoreRules, IGNORE_FILE},
+    index::AnimeIndex,
+    linker::{self, LinkMode},
+    matcher::{self, Decision},
+    migrate,
+    plan::{
+        self, LinkTask, Match, MatchReason, Operation, Plan, PlanFile, PlannedTask, Renaming,
+        Review, Snapshot,
+    },
+    release,
+    rename::{self, Rename, Template},
+    scan,
+    source_anime_map::{FileMap, FileType, Layout, MapPath, SourceAnimeMap},
+    store,
 };
 
+// 一次计划中匹配动漫用到的状态.
+#[derive(Default)]
+struct MatchState {
+    anime_cache: Cache,
+    names: NameIndex, // anime_cache 中的文件名索引.
+    reviews: Vec<Review>,
+    fingerprints: FingerprintCache,
+    index: AnimeIndex,
+    sizes: Option<HashMap<u64, Vec<(String, PathBuf)>>>, // 动漫文件按大小分组, 需要时才建立.
+}
+
 // data.yaml 的结构体.
 pub struct Data {
     pub data: RealData,
     // 从序列化中跳过.
-    source_map: HashMap<String, ()>,
+    source_map: HashMap<(String, String), ()>, // (root, source).
+    anime_dirs: HashMap<String, PathBuf>,      // 所有动漫文件夹共用的索引.
+    reviews: Vec<Review>,                      // 上一次计划中需要人工确认的源.
+    renames: Vec<Renaming>,                    // 只读运行中将要执行的重命名.
     config: Config,
 }
 
@@ -26,82 +57,180 @@ impl Data {
         Data {
             data: RealData::default(),
             source_map: HashMap::default(),
+            anime_dirs: HashMap::default(),
+            reviews: Vec::new(),
+            renames: Vec::new(),
             config,
         }
     }
 
-    // 从 yaml 文件中读取数据.
-    pub fn from_yaml(config: Config) -> Data {
+    // 从 yaml 文件中读取数据, 文件不存在时为空.
+    pub fn from_yaml(config: Config) -> Result<Data, Box<dyn Error>> {
         // 读取文件并且缓存已有数据.
-        let Ok(file) = File::open(&config.mapfile_path) else {
-            return Data::new(config);
+        let path = &config.mapfile_path;
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Data::new(config)),
+            Err(e) => return Err(format!("read {} failed: {}", path, e).into()),
         };
-        let real_data = RealData::from_file(file);
+        let mut real_data = RealData::from_str(&content).map_err(|e| {
+            format!(
+                "parse {} failed: {}, fix it or roll back with restore",
+                path, e
+            )
+        })?;
+        // 旧数据没有 root, 归入第一组文件夹.
+        if let Some(root) = config.roots.first() {
+            real_data.rename_root("", &root.name);
+        }
+        // 旧版本默认用源文件夹地址作为名称, 换成现在的名称.
+        for root in &config.roots {
+            if !config.roots.iter().any(|x| x.name == root.source) {
+                real_data.rename_root(&root.source, &root.name);
+            }
+        }
         let mut data: Data = Data {
             data: real_data,
             source_map: HashMap::new(),
+            anime_dirs: HashMap::new(),
+            reviews: Vec::new(),
+            renames: Vec::new(),
             config,
         };
 
         for i in &data.data.source_anime_maps {
-            let name = i.source.clone();
-            data.source_map.insert(name, ());
+            let key = (i.root.clone(), i.source.clone());
+            data.source_map.insert(key, ());
+        }
+        Ok(data)
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.config.action
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // 所属的文件夹组合, 不在本次运行的配置中时返回 None.
+    fn root_of(&self, map: &SourceAnimeMap) -> Option<&Root> {
+        self.config.roots.iter().find(|x| x.name == map.root)
+    }
+
+    pub fn push_map_from_dir(&mut self) -> Result<(), Box<dyn Error>> {
+        for root in self.config.roots.clone() {
+            self.push_map_from_root(&root)?;
         }
-        data
+        Ok(())
     }
 
-    pub fn push_map_from_dir(&mut self) {
-        let entries = fs::read_dir(&self.config.source_path);
-        for dir_entry in entries.unwrap() {
-            let dir_entry = dir_entry.unwrap();
-            let name = dir_entry.file_name().into_string().unwrap();
+    fn push_map_from_root(&mut self, root: &Root) -> Result<(), Box<dyn Error>> {
+        let rules = IgnoreRules::new(Path::new(&root.source), &self.config.ignore)?;
+        let mut entries = fs::read_dir(&root.source)
+            .and_then(|x| x.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("read {} failed: {}", root.source, e))?;
+        // 按名称排序, 同时读取时结果也不变.
+        entries.sort_by_key(|x| x.file_name());
+        let mut pending = Vec::new();
+        for dir_entry in entries {
+            // 名称不是 UTF-8 时无法记录到 map.
+            let Ok(name) = dir_entry.file_name().into_string() else {
+                if !self.config.dry_run {
+                    println!("skip non-UTF-8 source: {}", dir_entry.path().display());
+                }
+                continue;
+            };
+            if name == IGNORE_FILE {
+                continue;
+            }
 
             // 定义一个 push 函数, 根据不同的动作进行不同的处理.
-            let mut push_fn: fn(&mut RealData, String, FileType) = RealData::push_new_map;
+            let mut push_fn: fn(&mut RealData, SourceAnimeMap) = RealData::push_new_map;
 
-            if self.source_map.contains_key(&name) {
+            if self
+                .source_map
+                .contains_key(&(root.name.clone(), name.clone()))
+            {
                 match self.config.action {
                     Action::Renew => {
                         push_fn = RealData::push_renew_map;
-                        println!("renew anime source: {}", name);
+                        if !self.config.dry_run {
+                            println!("renew anime source: {}", name);
+                        }
                     }
                     _ => continue,
                 }
-            } else {
+            } else if !self.config.dry_run {
                 println!("new anime source: {}", name);
             }
-            let file_type = self.get_map_file_type(&name, &dir_entry);
-            push_fn(&mut self.data, name, file_type);
+            pending.push((push_fn, name, dir_entry));
+        }
+
+        // 子文件夹用多个线程同时读取, 再按顺序加入.
+        let maps = scan::map(pending, self.config.jobs, |(push_fn, name, dir_entry)| {
+            let relative = PathBuf::from(&name);
+            let mut map = bulid_anime_map(&root.name, name, "".to_string(), FileType::File);
+            self.set_map_file_type(&mut map, &relative, &dir_entry, &rules);
+            (push_fn, map)
+        });
+        for (push_fn, map) in maps {
+            push_fn(&mut self.data, map);
         }
+        Ok(())
     }
 
-    // 获取文件类型.
-    pub fn get_map_file_type(&self, name: &str, dir_entry: &DirEntry) -> FileType {
-        let fs_file_type = dir_entry.file_type().unwrap();
+    // 获取文件类型, 被忽略的 map 记录匹配的规则.
+    // relative 是相对于源文件夹的路径, 嵌套的子 map 递归获取.
+    pub fn set_map_file_type(
+        &self,
+        map: &mut SourceAnimeMap,
+        relative: &Path,
+        dir_entry: &DirEntry,
+        rules: &IgnoreRules,
+    ) {
+        let is_dir = dir_entry.file_type().is_ok_and(|x| x.is_dir());
+        map.ignored_by = rules.matched(relative, is_dir);
+        map.file_type = match map.ignored_by {
+            Some(_) => FileType::Other,
+            None => self.get_map_file_type(&map.root, relative, dir_entry, rules),
+        };
+    }
+
+    fn get_map_file_type(
+        &self,
+        root: &str,
+        relative: &Path,
+        dir_entry: &DirEntry,
+        rules: &IgnoreRules,
+    ) -> FileType {
+        let is_dir = |x: &DirEntry| x.file_type().is_ok_and(|x| x.is_dir());
         let mut file_type: FileType = FileType::File;
-        if fs_file_type.is_file() {
-            // 排除 .parts 文件.
-            if name.ends_with(".parts") {
-                file_type = FileType::Other
-            }
-        } else if fs_file_type.is_dir() {
+        if is_dir(dir_entry) {
             file_type = FileType::Dir;
             // 判断是否嵌套.
             // 先判断是否有其他文件,
-            // 再生成子目录的 SourceAnimeMap.
-            let (dir, other): (Vec<_>, Vec<_>) = fs::read_dir(dir_entry.path())
+            // 再生成子目录的 SourceAnimeMap, 子目录也可以是嵌套的.
+            let (mut dir, other): (Vec<_>, Vec<_>) = fs::read_dir(dir_entry.path())
                 .into_iter()
                 .flatten()
                 .flatten()
-                .partition(|x| x.file_type().unwrap().is_dir());
-            if other.is_empty() {
+                .partition(is_dir);
+            dir.sort_by_key(|x| x.file_name());
+            // 子文件夹的名称不是 UTF-8 时无法记录, 整个文件夹作为一个源.
+            let names: Option<Vec<_>> = dir
+                .iter()
+                .map(|x| x.file_name().into_string().ok())
+                .collect();
+            if let Some(names) = names.filter(|_| other.is_empty() && !dir.is_empty()) {
                 let maps = dir
                     .iter()
-                    .map(|x| SourceAnimeMap {
-                        source: x.file_name().into_string().unwrap(),
-                        anime: "".to_string(),
-                        active: true,
-                        file_type: FileType::Dir,
+                    .zip(names)
+                    .map(|(x, name)| {
+                        let relative = relative.join(&name);
+                        let mut map = bulid_anime_map(root, name, "".to_string(), FileType::Dir);
+                        self.set_map_file_type(&mut map, &relative, x, rules);
+                        map
                     })
                     .collect();
                 file_type = FileType::Nesting(maps);
@@ -110,187 +239,582 @@ impl Data {
         file_type
     }
 
+    // 合并所有动漫文件夹, 同名的动漫以先出现的为准.
     pub fn push_anime_from_dir(&mut self) -> Result<(), Box<dyn Error>> {
-        let anime_dir = &self.config.anime_path;
-        fs::read_dir(anime_dir)?
-            .flatten()
-            .flat_map(|dir_entry| dir_entry.file_name().into_string())
-            .for_each(|name| self.data.push_anime(name.to_string()));
+        for root in &self.config.roots {
+            fs::read_dir(&root.anime)?
+                .flatten()
+                .filter_map(|dir_entry| {
+                    Some((dir_entry.file_name().into_string().ok()?, dir_entry))
+                })
+                .for_each(|(name, dir_entry)| {
+                    self.anime_dirs
+                        .entry(name.clone())
+                        .or_insert(dir_entry.path());
+                    self.data.push_anime(name);
+                });
+        }
 
         Ok(())
     }
 
+    // 动漫的文件夹, 不存在时放到 map 所属的动漫文件夹下.
+    fn anime_dir(&self, anime: &str, root: &Root) -> PathBuf {
+        match self.anime_dirs.get(anime) {
+            Some(dir) => dir.clone(),
+            None => Path::new(&root.anime).join(anime),
+        }
+    }
+
+    // 写入前备份旧文件, 写入失败时旧文件保持不变.
     pub fn write_yaml(&self) -> Result<(), Box<dyn Error>> {
-        let _ = fs::write(
-            &self.config.mapfile_path,
-            serde_yaml::to_string(&self.data).unwrap().as_str(),
-        );
+        let path = Path::new(&self.config.mapfile_path);
+        let content = serde_yaml::to_string(&self.data)?;
+        store::backup(path, content.as_bytes(), self.config.backups)
+            .map_err(|e| format!("backup {} failed: {}", path.display(), e))?;
+        store::write_atomic(path, content.as_bytes())
+            .map_err(|e| format!("write {} failed: {}", path.display(), e))?;
         Ok(())
     }
 
-    pub fn map_animes(&mut self) -> Result<(), Box<dyn Error>> {
-        let mut anime_cache = Cache::default();
+    // 手动修改 map, 嵌套的 map 用 "Collection/Series/Season" 表示.
+    pub fn edit_map(&mut self, edit: &EditArgs) -> Result<(), Box<dyn Error>> {
+        let path = self.data.find_map(edit.root.as_deref(), &edit.source)?;
+        let sources = &path.sources[1..];
+
+        let map = self.data.top_map_mut(&path)?;
+        if let Some(anime) = &edit.anime {
+            map.set_anime(sources, anime)?;
+        }
+        if let Some(active) = edit.active {
+            map.set_active(sources, active)?;
+        }
+        if let Some(link_modes) = &edit.link_mode {
+            let map = map.get_mut(sources).ok_or("source not found")?;
+            map.link_modes = link_modes.clone();
+        }
+        if edit.sub_path.is_some() || edit.layout.is_some() || edit.season.is_some() {
+            let map = map.get_mut(sources).ok_or("source not found")?;
+            if let Some(sub_path) = &edit.sub_path {
+                map.sub_path = sub_path.clone();
+            }
+            if let Some(layout) = edit.layout {
+                map.layout = layout;
+            }
+            if edit.season.is_some() {
+                map.season = edit.season;
+            }
+        }
+        if let Some(template) = &edit.rename {
+            if !template.is_empty() {
+                Template::new(template)?;
+            }
+            let map = map.get_mut(sources).ok_or("source not found")?;
+            map.rename = Some(template.clone());
+        }
+        if let Some(file) = &edit.file {
+            let map = map.get_mut(sources).ok_or("source not found")?;
+            if map.file_type != FileType::Dir {
+                return Err(format!("{} isn't a folder, can't map its files", path).into());
+            }
+            let file = map.file_mut(file);
+            if let Some(destination) = &edit.destination {
+                file.destination = destination.clone();
+            }
+            if let Some(include) = edit.include {
+                file.include = include;
+            }
+        }
+        Ok(())
+    }
+
+    // 输出所有 map 的状态.
+    pub fn print_status(&self) {
+        fn print_map(map: &SourceAnimeMap, indent: usize) {
+            let state = if map.file_type.is_other() {
+                "skip"
+            } else if map.active {
+                "todo"
+            } else {
+                "done"
+            };
+            let mut linked_by = match (&map.ignored_by, map.linked_by) {
+                (Some(rule), _) => format!(" (ignored by {})", rule),
+                (None, Some(mode)) => format!(" ({})", mode),
+                (None, None) => String::new(),
+            };
+            if !map.renamed.is_empty() {
+                linked_by.push_str(&format!(" ({} renamed)", map.renamed.len()));
+            }
+            println!(
+                "{:indent$}[{}] {} -> {}{}",
+                "",
+                state,
+                map.source,
+                map.anime,
+                linked_by,
+                indent = indent
+            );
+            for file in &map.files {
+                let destination = match (file.include, file.destination.as_str()) {
+                    (false, _) => "excluded".to_string(),
+                    (true, "") => "with folder".to_string(),
+                    (true, destination) => format!("-> {}", destination),
+                };
+                let linked_by = file.linked_by.map(|x| format!(" ({})", x));
+                println!(
+                    "{:indent$}file {} {}{}",
+                    "",
+                    file.source,
+                    destination,
+                    linked_by.unwrap_or_default(),
+                    indent = indent + 4
+                );
+            }
+            if let FileType::Nesting(maps) = &map.file_type {
+                maps.iter().for_each(|x| print_map(x, indent + 4));
+            }
+        }
+
         let maps = &self.data.source_anime_maps;
-        let reflink_queue = self.need_reflink_anime_indexes(maps, &mut anime_cache);
-        let Some(reflink_queue) = reflink_queue else {
-            return Ok(());
+        maps.iter().for_each(|x| print_map(x, 0));
+        println!(
+            "{} sources, {} todo, {} animes.",
+            maps.len(),
+            maps.iter().filter(|x| x.active()).count(),
+            self.data.animes.len()
+        );
+    }
+
+    // 匹配时需要人工确认的源, 它们不会被链接.
+    pub fn reviews(&self) -> &[Review] {
+        &self.reviews
+    }
+
+    pub fn print_reviews(&self) {
+        if self.reviews.is_empty() {
+            return;
+        }
+        println!("Needs review ({}):", self.reviews.len());
+        self.reviews.iter().for_each(|x| print!("{}", x));
+    }
+
+    // 返回匹配到的链接任务, 只读运行时不会链接.
+    pub fn map_animes(&mut self) -> Result<Vec<LinkTask>, Box<dyn Error>> {
+        let reflink_queue = self.plan_animes();
+        if let (Action::Link, false) = (&self.config.action, self.config.dry_run) {
+            self.link_tasks(&reflink_queue);
+        }
+        Ok(reflink_queue)
+    }
+
+    // 计划阶段: 匹配动漫并记录到 map, 不会链接.
+    pub fn plan_animes(&mut self) -> Vec<LinkTask> {
+        let map_path = Path::new(&self.config.mapfile_path);
+        let fingerprints_path = FingerprintCache::path(map_path);
+        let index_path = AnimeIndex::path(map_path);
+        let rules = self.index_rules();
+        let mut state = MatchState {
+            fingerprints: FingerprintCache::load(&fingerprints_path),
+            index: match self.config.rebuild_index {
+                true => AnimeIndex::new(&rules),
+                false => AnimeIndex::load(&index_path, &rules),
+            },
+            ..Default::default()
+        };
+        let maps = &self.data.source_anime_maps;
+        let reflink_queue = self.need_reflink_anime_paths(maps, None, &mut state);
+        // 只读运行不写指纹缓存和动漫索引.
+        if !self.config.dry_run {
+            if let Err(e) = state.fingerprints.save(&fingerprints_path) {
+                println!("save {} failed: {}", fingerprints_path.display(), e);
+            }
+            if let Err(e) = state.index.save(&index_path) {
+                println!("save {} failed: {}", index_path.display(), e);
+            }
+        }
+        self.reviews = state.reviews;
+        self.set_task_anime_name(&reflink_queue);
+        reflink_queue
+    }
+
+    // 记录匹配到的动漫和解析出的链接地址.
+    fn set_task_anime_name(&mut self, tasks: &[LinkTask]) {
+        let anime_names: Vec<_> = tasks
+            .iter()
+            .map(|x| (x.path.clone(), x.anime.clone()))
+            .collect();
+        self.data.set_anime_name(&anime_names);
+        for task in tasks {
+            let Some((_, destination)) = self.map_destination(&task.path) else {
+                continue;
+            };
+            // 记录相对于动漫文件夹组合的路径, 不在组合下时是绝对路径.
+            let base = self
+                .data
+                .get_map(&task.path)
+                .and_then(|x| self.root_of(x))
+                .map(|x| PathBuf::from(&x.anime))
+                .unwrap_or_default();
+            let destination = destination.strip_prefix(&base).unwrap_or(&destination);
+            let map = self
+                .data
+                .top_map_mut(&task.path)
+                .ok()
+                .and_then(|x| x.get_mut(&task.path.sources[1..]));
+            if let Some(map) = map {
+                map.destination = destination.to_string_lossy().to_string();
+            }
+        }
+    }
+
+    // 执行阶段: 链接并记录结果.
+    fn link_tasks(&mut self, tasks: &[LinkTask]) {
+        let linked = self.reflink(tasks);
+        let modes: Vec<_> = linked.iter().map(|x| (x.path.clone(), x.mode)).collect();
+        self.data.set_map_linked_by(&modes);
+        for x in &linked {
+            self.data.set_files_linked_by(&x.path, &x.files);
+            match self.rename_map(&x.path) {
+                Ok(renamed) => self.data.set_map_renamed(&x.path, renamed.records),
+                Err(e) => println!("rename error: {}", e),
+            }
+        }
+        let successed: Vec<_> = linked.into_iter().map(|x| (x.path, false)).collect();
+        self.data.set_map_active(&successed);
+    }
+
+    // 重命名已链接的 map, 或者撤销重命名. 没有指定源时处理所有已链接的 map.
+    pub fn rename_maps(&mut self, args: &RenameArgs) -> Result<(), Box<dyn Error>> {
+        let paths = match &args.source {
+            Some(source) => vec![self.data.find_map(args.root.as_deref(), source)?],
+            None => linked_paths(&self.data.source_anime_maps, None),
         };
-        self.data.set_anime_name(&reflink_queue);
-        if let Action::Reflink = self.config.action {
-            let successed_index = self.reflink(&reflink_queue);
-            self.data.set_map_active(&successed_index);
+        for path in paths {
+            let renamed = match args.undo {
+                true => self.undo_rename(&path)?,
+                false => self.rename_map(&path)?,
+            };
+            if !self.config.dry_run {
+                self.data.set_map_renamed(&path, renamed.records);
+                continue;
+            }
+            for (from, to) in renamed.moves {
+                self.renames.push(Renaming { from, to });
+            }
         }
         Ok(())
     }
 
-    // 获取需要 relink 的 anime index.
+    // 生成计划文件, 记录源和目标当前的状态.
+    pub fn plan_file(&self, tasks: &[LinkTask]) -> Result<PlanFile, Box<dyn Error>> {
+        let mut planned = Vec::new();
+        for task in tasks {
+            let (source, destination) = self
+                .map_destination(&task.path)
+                .ok_or(format!("root not configured: {}", task.path.root))?;
+            let map = self.data.get_map(&task.path).ok_or("source not found")?;
+            planned.push(PlannedTask {
+                task: task.clone(),
+                map: map.clone(),
+                source_state: Snapshot::take(&source)?,
+                destination_state: Snapshot::take(&destination)?,
+            });
+        }
+        Ok(PlanFile {
+            created: Utc::now().to_rfc3339(),
+            tasks: planned,
+        })
+    }
+
+    // 执行计划文件, 源或目标在计划后发生变化时拒绝执行.
+    // map 按地址查找, 所以计划后新增的 map 不影响执行.
+    pub fn apply_plan(&mut self, plan: &PlanFile) -> Result<Vec<LinkTask>, Box<dyn Error>> {
+        let tasks: Vec<_> = plan.tasks.iter().map(|x| x.task.clone()).collect();
+        if let Some(task) = tasks.iter().find(|x| self.data.get_map(&x.path).is_none()) {
+            let err = format!(
+                "map file changed since the plan was made: source not found: {}",
+                task.path
+            );
+            return Err(err.into());
+        }
+        self.set_task_anime_name(&tasks);
+
+        for planned in &plan.tasks {
+            let path = &planned.task.path;
+            let map = self.data.get_map(path).ok_or("source not found")?;
+            if let Some(change) =
+                plan::changes(slice::from_ref(&planned.map), slice::from_ref(map)).first()
+            {
+                let err = format!(
+                    "map file changed since the plan was made: {} {}: {:?} -> {:?}",
+                    path, change.field, change.before, change.after
+                );
+                return Err(err.into());
+            }
+            let (source, destination) = self
+                .map_destination(path)
+                .ok_or(format!("root not configured: {}", path.root))?;
+            let changed = [
+                (&source, &planned.source_state),
+                (&destination, &planned.destination_state),
+            ]
+            .into_iter()
+            .map(|(path, state)| Ok((path, state.first_difference(&Snapshot::take(path)?))))
+            .collect::<Result<Vec<_>, io::Error>>()?;
+            if let Some((path, Some(file))) = changed.into_iter().find(|(_, x)| x.is_some()) {
+                let err = format!(
+                    "{} changed since the plan was made: {}",
+                    path.display(),
+                    file
+                );
+                return Err(err.into());
+            }
+        }
+
+        if !self.config.dry_run {
+            self.link_tasks(&tasks);
+        }
+        Ok(tasks)
+    }
+
+    // 根据运行前的数据和链接任务生成计划.
+    pub fn build_plan(&self, before: &RealData, tasks: &[LinkTask]) -> Plan {
+        let mut plan = Plan::default();
+        plan.diff_maps(&before.source_anime_maps, &self.data.source_anime_maps);
+        plan.reviews = self.reviews.clone();
+        plan.renames = self.renames.clone();
+        for task in tasks {
+            let Some(map) = self.data.get_map(&task.path) else {
+                continue;
+            };
+            plan.matches.push(Match {
+                root: map.root.clone(),
+                source: task.path.to_string(),
+                anime: task.anime.clone(),
+                reason: task.reason.clone(),
+            });
+            let Some((source, destination)) = self.map_destination(&task.path) else {
+                continue;
+            };
+            let link_modes = self.link_modes_of(map);
+            plan.operations.push(Operation {
+                source,
+                destination,
+                link_modes: link_modes.to_vec(),
+            });
+            for file in &map.files {
+                if let Some((source, destination)) = self.file_destination(&task.path, file) {
+                    plan.operations.push(Operation {
+                        source,
+                        destination,
+                        link_modes: link_modes.to_vec(),
+                    });
+                }
+            }
+        }
+        plan
+    }
+
+    // 获取需要 relink 的 map 的地址, 嵌套的 map 递归获取.
     // 因为无法同时更改 map 的 anime, 所以把 anime name 也存进去.
-    fn need_reflink_anime_indexes(
+    fn need_reflink_anime_paths(
         &self,
         source_anime_maps: &[SourceAnimeMap],
-        anime_cache: &mut Cache,
-    ) -> Option<Vec<(usize, usize, String)>> {
-        let mut indexes = Vec::<(usize, usize, String)>::new();
+        parent: Option<&MapPath>,
+        state: &mut MatchState,
+    ) -> Vec<LinkTask> {
+        let mut tasks = Vec::<LinkTask>::new();
         source_anime_maps
             .iter()
-            .enumerate()
-            .filter(|(_, map)| map.active())
-            .for_each(|(i, map)| {
+            .filter(|map| map.active() && self.root_of(map).is_some())
+            .for_each(|map| {
+                let path = match parent {
+                    Some(parent) => parent.child(&map.source),
+                    None => MapPath::new(&map.root, &map.source),
+                };
                 if let FileType::Nesting(nesting) = &map.file_type {
-                    let nesting_indexes = self.need_reflink_anime_indexes(nesting, anime_cache);
-                    if let Some(nesting_indexes) = nesting_indexes {
-                        indexes.extend(nesting_indexes.iter().map(|x| (i, x.0, x.2.clone())));
-                    }
+                    tasks.extend(self.need_reflink_anime_paths(nesting, Some(&path), state));
                 } else if map.anime.is_empty() {
-                    let source = map.source.clone();
-                    let anime = self.find_exist_anime(source, anime_cache);
-                    if let Some(anime) = anime {
-                        indexes.push((i, 0, anime));
+                    match self.find_exist_anime(&path, map, state) {
+                        Decision::Matched(candidate) => tasks.push(LinkTask {
+                            path,
+                            anime: candidate.anime,
+                            reason: candidate.reason,
+                        }),
+                        Decision::Review(candidates) => state.reviews.push(Review {
+                            root: map.root.clone(),
+                            source: path.to_string(),
+                            candidates,
+                        }),
+                        Decision::Unmatched => {}
                     }
                 } else {
-                    indexes.push((i, 0, map.anime.clone()));
+                    tasks.push(LinkTask {
+                        path,
+                        anime: map.anime.clone(),
+                        reason: MatchReason::Recorded,
+                    });
                 }
             });
-        if !indexes.is_empty() {
-            Some(indexes)
-        } else {
-            None
-        }
+        tasks
     }
 
-    fn reflink(&self, reflink_queue: &Vec<(usize, usize, String)>) -> Vec<(usize, usize, bool)> {
-        let mut successed_index: Vec<(usize, usize, bool)> = Vec::new();
+    fn reflink(&self, reflink_queue: &[LinkTask]) -> Vec<Linked> {
+        let mut successed: Vec<Linked> = Vec::new();
 
-        for i in reflink_queue {
-            let map = self.data.get_map_at_indexes((i.0, i.1));
-            match self.reflink_map(map) {
-                Ok(_) => {
-                    successed_index.push((i.0, i.1, false));
+        for path in reflink_queue.iter().map(|x| &x.path) {
+            match self.reflink_map(path) {
+                Ok(linked) => {
+                    successed.push(linked);
                 }
                 Err(e) => println!("reflink error:{}", e),
             }
         }
-        successed_index
+        successed
     }
 
-    fn find_exist_anime(&self, source: String, anime_cache: &mut Cache) -> Option<String> {
-        if let Some(anime) = anime_cache
-            .iter()
-            .find(|(_, c)| c.contains(&source))
-            .map(|(a, _)| a)
-        {
-            return Some(anime.clone());
-        }
-
-        let source_path = Path::new(&self.config.source_path).join(&source);
+    // 对所有动漫排序后决定是否匹配, 并列或者置信度太低时交给人工确认.
+    // 按文件名无法确定时再比较文件内容.
+    fn find_exist_anime(
+        &self,
+        path: &MapPath,
+        map: &SourceAnimeMap,
+        state: &mut MatchState,
+    ) -> Decision {
+        let Some((source_path, _)) = self.map_paths(path) else {
+            return Decision::Unmatched;
+        };
         let mut source_set: HashSet<String> = HashSet::new();
-        if let Ok(entries) = fs::read_dir(source_path) {
+        let mut source_files = Vec::new();
+        if let Ok(entries) = fs::read_dir(&source_path) {
             entries
                 .flatten()
-                .filter_map(Self::filter_file_dir)
-                .for_each(|(name, _)| {
+                .filter_map(|x| Self::filter_file_dir(x, &self.config, 1))
+                .for_each(|(name, path)| {
+                    if path.as_os_str() == "" {
+                        source_files.push((name.clone(), source_path.join(&name)));
+                    }
                     source_set.insert(name);
                 });
-        };
+        } else if source_path.is_file() && self.config.media.is_video(&map.source) {
+            source_files.push((map.source.clone(), source_path.clone()));
+            source_set.insert(map.source.clone());
+        }
 
-        if let Some(anime) = anime_cache
+        // 所有动漫都要参与排序, 还没有读取的一起读取.
+        let uncached: Vec<_> = self
+            .data
+            .animes
             .iter()
-            .find(|(_, c)| c.contains_set(&source_set))
-            .map(|(a, _)| a)
-        {
-            return Some(anime.clone());
-        }
-
-        // cannot borrow `*self` as mutable more than once at a time
-        // second mutable borrow occurs here
-        //
-        // closure requires unique access to `*self`
-        // but it is already borrowed closure construction occurs her
-        //
-        // let animes = self.data.animes.clone();
-        // for anime in &animes {
-        //     if self.anime_tree.contains_key(anime) {
-        //         continue;
-        //     }
-        //     let tree = self.fetch_anime_tree( anime);
-        //     if tree.contains(&source) {
-        //         return Some(anime.clone());
-        //     }
-        // }
-        for i in 0..self.data.animes.len() {
-            let anime = &self.data.animes[i].clone();
-
-            if anime_cache.contains_key(anime) {
+            .filter(|x| !state.anime_cache.contains_key(x))
+            .cloned()
+            .collect();
+        self.fetch_anime_caches(&uncached, state);
+
+        let animes: Vec<_> = state.anime_cache.iter().map(|(a, _)| a.as_str()).collect();
+        let rank = |contents: &HashMap<String, HashSet<String>>| {
+            let candidates = matcher::rank(
+                &map.source,
+                &source_set,
+                &animes,
+                &state.names,
+                contents,
+                self.config.match_threshold,
+            );
+            matcher::decide(candidates, self.config.min_confidence)
+        };
+        let decision = rank(&HashMap::new());
+        if matches!(decision, Decision::Matched(_)) {
+            return decision;
+        }
+
+        // 动漫文件夹中的文件可能已经改名, 用指纹找到内容相同的文件.
+        // 大小不同的内容一定不同, 只计算大小和源文件相同的文件的指纹.
+        let sizes = state
+            .sizes
+            .get_or_insert_with(|| self.anime_sizes(&state.anime_cache));
+        let mut contents: HashMap<String, HashSet<String>> = HashMap::new();
+        for (name, path) in &source_files {
+            let Some(files) = fs::metadata(path).ok().and_then(|x| sizes.get(&x.len())) else {
                 continue;
-            }
-            let tree = self.fetch_anime_cache(anime, anime_cache);
-            if tree.contains(&source) {
-                return Some(anime.to_string());
+            };
+            let Some(fingerprint) = state.fingerprints.get(path) else {
+                continue;
+            };
+            for (anime, file) in files {
+                if state.fingerprints.get(file) == Some(fingerprint) {
+                    contents
+                        .entry(anime.clone())
+                        .or_default()
+                        .insert(name.clone());
+                }
             }
         }
+        match contents.is_empty() {
+            true => decision,
+            false => rank(&contents),
+        }
+    }
 
-        for (anime, set) in anime_cache {
-            if set.contains_set(&source_set) {
-                return Some(anime.clone());
+    // 已经读取的动漫索引中的视频文件按大小分组, 多个文件同时读取大小.
+    fn anime_sizes(&self, anime_cache: &Cache) -> HashMap<u64, Vec<(String, PathBuf)>> {
+        let mut files = Vec::new();
+        for (anime, cache) in anime_cache.iter() {
+            let Some(anime_dir) = self.anime_dirs.get(anime) else {
+                continue;
+            };
+            files.extend(
+                cache
+                    .walk()
+                    .filter(|(_, x)| !x.is_map())
+                    .map(|(path, _)| (anime.clone(), anime_dir.join(path))),
+            );
+        }
+        let files = scan::map(files, self.config.jobs, |(anime, path)| {
+            let size = fs::metadata(&path).map(|x| x.len());
+            (size, anime, path)
+        });
+        let mut sizes: HashMap<u64, Vec<(String, PathBuf)>> = HashMap::new();
+        for (size, anime, path) in files {
+            if let Ok(size) = size {
+                sizes.entry(size).or_default().push((anime, path));
             }
         }
-        None
+        sizes
     }
 
-    fn fetch_anime_cache<'a>(&'a self, anime: &str, anime_cache: &'a mut Cache) -> &Cache {
-        let cache = anime_cache.entry(anime).unwrap().or_default().as_mut();
-        let anime_dir = Path::new(&self.config.anime_path).join(anime);
-
-        Self::fetch_cache(cache, anime_dir);
-        cache
+    // 从动漫索引中获取文件, 索引只重新读取有变化的文件夹.
+    // 多个动漫文件夹同时读取, 同时建立文件名索引.
+    fn fetch_anime_caches(&self, animes: &[String], state: &mut MatchState) {
+        let mut dirs = Vec::new();
+        for anime in animes {
+            match self.anime_dirs.get(anime) {
+                Some(dir) => dirs.push((anime, dir.clone())),
+                None => state.anime_cache.insert(anime, Cache::default()),
+            }
+        }
+        let paths: Vec<_> = dirs.iter().map(|(_, x)| x.clone()).collect();
+        let filter = |x, depth| Self::filter_file_dir(x, &self.config, depth);
+        let caches = state.index.fetch_all(&paths, &filter, self.config.jobs);
+        for ((anime, _), cache) in dirs.into_iter().zip(caches) {
+            state.names.insert(anime, &cache);
+            state.anime_cache.insert(anime, cache);
+        }
     }
 
-    // 递归获取文件.
-    fn fetch_cache<P: AsRef<Path>>(cache: &mut Cache, dir_path: P) {
-        let Ok(entries) = fs::read_dir(dir_path) else {
-            return;
-        };
-        // HACK: 关于 flatten 和 filter_map 的讨论:
-        // https://users.rust-lang.org/t/where-is-flatten-skipping-none-documented/89255/21
-        // https://github.com/rust-lang/rust-clippy/issues/9377
-        // https://github.com/rust-lang/rust/pull/99230
-        // https://rust.godbolt.org/z/aG444qGdW
-        // filter_map(|x| x), filter_map(identity), flatten.
-        entries
-            .flatten()
-            .filter_map(Self::filter_file_dir)
-            .for_each(|(name, path)| {
-                if path.as_os_str() == "" {
-                    cache.insert_none(&name);
-                } else if let Some(new) = cache.insert_default(&name) {
-                    Self::fetch_cache(new, path);
-                }
-            });
+    // 动漫索引的过滤规则, 规则变化后索引失效.
+    fn index_rules(&self) -> String {
+        format!("{:?} {:?}", self.config.media.video, self.config.descend)
     }
 
-    // 过滤非视频文件或者特殊文件夹.
+    // 过滤非视频文件和不需要进入的文件夹.
     // 返回文件名和文件夹路径.
-    fn filter_file_dir(dir_entry: DirEntry) -> Option<(String, PathBuf)> {
+    fn filter_file_dir(
+        dir_entry: DirEntry,
+        config: &Config,
+        depth: usize,
+    ) -> Option<(String, PathBuf)> {
         let Ok(file_type) = dir_entry.file_type() else {
             return None;
         };
@@ -300,156 +824,566 @@ impl Data {
 
         if file_type.is_file() {
             // 排除非视频文件.
-            if [".mkv", ".mp4", ".avi"]
-                .iter()
-                .map(|suffixes| name.ends_with(suffixes))
-                .any(|x| x)
-            {
+            if config.media.is_video(&name) {
                 return Some((name, "".into()));
             }
         } else if file_type.is_dir() {
-            // 当文件夹名称超过 20 个字符，或者以 "Season" 开头时,
-            // 返回文件夹路径备用.
-            if name.len() > 20 || name.to_lowercase().starts_with("season") {
+            // 符合遍历规则时返回文件夹路径备用.
+            if config.descend.should_descend(&name, depth) {
                 return Some((name, dir_entry.path()));
             }
         }
         None
     }
 
-    fn reflink_map(&self, source_anime_map: &SourceAnimeMap) -> Result<(), Box<dyn Error>> {
-        let source = &format!("{}/{}", self.config.source_path, source_anime_map.source);
-        let anime = &format!("{}/{}", self.config.anime_path, source_anime_map.anime);
+    // map 的源地址和动漫文件夹地址, 嵌套的 map 的源地址包含每一层.
+    fn map_paths(&self, path: &MapPath) -> Option<(PathBuf, PathBuf)> {
+        let map = self.data.get_map(path)?;
+        let root = self.root_of(map)?;
+        let source: PathBuf = [root.source.as_str()]
+            .into_iter()
+            .chain(path.sources.iter().map(String::as_str))
+            .collect();
+        Some((source, self.anime_dir(&map.anime, root)))
+    }
 
-        let os_type = std::env::consts::OS;
-        match os_type {
-            "linux" => {
-                println!("Source: {}, Anime: {}.", source, anime);
-                if fs::read_dir(anime).is_err() {
-                    fs::create_dir(anime).unwrap();
-                }
-                let output = Command::new("cp")
-                    .arg("--archive")
-                    .arg("-r")
-                    .arg("--reflink=always")
-                    .arg(source)
-                    .arg(anime)
-                    .output();
-                match output {
-                    Ok(_) => Ok(()),
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        Err(Box::new(e))
-                    }
-                }
+    // map 的源地址和链接后的地址, 按 map 的子路径和布局解析.
+    // flatten 和 season 布局的文件夹链接后的地址是放内容的文件夹.
+    fn map_destination(&self, path: &MapPath) -> Option<(PathBuf, PathBuf)> {
+        let (source, mut destination) = self.map_paths(path)?;
+        let map = self.data.get_map(path)?;
+        if !map.sub_path.is_empty() {
+            destination.push(&map.sub_path);
+        }
+        if map.layout == Layout::Season {
+            let season = map
+                .season
+                .or_else(|| release::season_of(&map.source))
+                .unwrap_or(1);
+            destination.push(Layout::season_dir(season));
+        }
+        let keep_name = map.layout.is_nested() || map.file_type != FileType::Dir;
+        if let (true, Some(name)) = (keep_name, source.file_name()) {
+            destination.push(name);
+        }
+        Some((source, destination))
+    }
+
+    // 单独映射的文件的源地址和链接后的地址, 按文件夹映射链接或不链接时为 None.
+    fn file_destination(&self, path: &MapPath, file: &FileMap) -> Option<(PathBuf, PathBuf)> {
+        if !file.include || file.destination.is_empty() {
+            return None;
+        }
+        let map = self.data.get_map(path)?;
+        let root = self.root_of(map)?;
+        let (source, _) = self.map_paths(path)?;
+        Some((
+            source.join(&file.source),
+            Path::new(&root.anime).join(&file.destination),
+        ))
+    }
+
+    // map 单独指定的回退链优先.
+    fn link_modes_of<'a>(&'a self, map: &'a SourceAnimeMap) -> &'a [LinkMode] {
+        if map.link_modes.is_empty() {
+            &self.config.link_modes
+        } else {
+            &map.link_modes
+        }
+    }
+
+    // 按文件夹映射链接的文件: 需要链接的类别, 没有单独映射, 并且没有被忽略.
+    // 忽略规则和读取源文件夹时相同, 路径相对于组合的源文件夹.
+    fn folder_filter<'a>(
+        &'a self,
+        map: &SourceAnimeMap,
+        source: &Path,
+    ) -> Result<impl Fn(&Path) -> bool + 'a, String> {
+        let root = self
+            .root_of(map)
+            .map(|x| PathBuf::from(&x.source))
+            .ok_or(format!("root not configured: {}", map.root))?;
+        let rules = IgnoreRules::new(&root, &self.config.ignore)?;
+        let overrides: HashSet<_> = map
+            .files
+            .iter()
+            .filter(|x| x.is_override())
+            .map(|x| source.join(&x.source))
+            .collect();
+        Ok(move |path: &Path| {
+            let ignored = path
+                .strip_prefix(&root)
+                .is_ok_and(|x| rules.matched(x, false).is_some());
+            self.config.media.should_link(path) && !overrides.contains(path) && !ignored
+        })
+    }
+
+    // 按文件夹映射链接后的文件的地址, 使用原文件名.
+    fn linked_files(&self, path: &MapPath) -> Vec<PathBuf> {
+        let (Some((source, destination)), Some(map)) =
+            (self.map_destination(path), self.data.get_map(path))
+        else {
+            return Vec::new();
+        };
+        if map.file_type != FileType::Dir {
+            return vec![destination];
+        }
+        let Ok(filter) = self.folder_filter(map, &source) else {
+            return Vec::new();
+        };
+        let mut files = Vec::new();
+        collect_files(&source, &mut files);
+        files.sort();
+        files
+            .into_iter()
+            .filter(|x| filter(x))
+            .filter_map(|file| match map.layout {
+                Layout::Flatten => Some(destination.join(file.file_name()?)),
+                _ => Some(destination.join(file.strip_prefix(&source).ok()?)),
+            })
+            .collect()
+    }
+
+    // map 单独指定的模板优先.
+    fn rename_template(&self, map: &SourceAnimeMap) -> Result<Option<Template>, String> {
+        match map.rename.as_deref() {
+            Some("") => Ok(None),
+            Some(template) => Template::new(template).map(Some),
+            None => Ok(self.config.rename.clone()),
+        }
+    }
+
+    // 按模板重命名 map 链接后的文件, 返回新的重命名记录.
+    // 新文件名总是从原文件名计算, 已经重命名过的文件从上一次的结果移动,
+    // 所以换了模板或者重新链接后都可以重复执行.
+    fn rename_map(&self, path: &MapPath) -> Result<Renamed, Box<dyn Error>> {
+        let map = self.data.get_map(path).ok_or("source not found")?;
+        let Some(template) = self.rename_template(map)? else {
+            return Ok(Renamed {
+                records: map.renamed.clone(),
+                moves: Vec::new(),
+            });
+        };
+        let root = self.root_of(map).ok_or("root not configured")?;
+        let base = Path::new(&root.anime);
+        let season = map
+            .season
+            .or_else(|| release::season_of(&map.source))
+            .unwrap_or(1);
+        let old: Vec<_> = map.renamed.iter().map(|x| x.paths(base)).collect();
+        let current = |from: &PathBuf| match from.exists() {
+            true => from.clone(),
+            false => old
+                .iter()
+                .find(|x| &x.0 == from)
+                .map_or(from.clone(), |x| x.1.clone()),
+        };
+
+        let files = self.linked_files(path);
+        let pairs = rename::plan(&files, &template, &self.config.media, &map.anime, season);
+        let moves: Vec<_> = pairs
+            .iter()
+            .map(|(from, to)| (current(from), to.clone()))
+            .filter(|(from, to)| from != to)
+            .collect();
+        if self.config.dry_run {
+            return Ok(Renamed {
+                records: map.renamed.clone(),
+                moves,
+            });
+        }
+        let replaceable: Vec<_> = old.iter().map(|x| x.1.clone()).collect();
+        let (_, errors) = rename::rename_all(&moves, &replaceable);
+        for error in &errors {
+            println!("rename error: {}", error);
+        }
+
+        // 保留这次没有处理的旧记录.
+        let mut renamed: Vec<_> = map
+            .renamed
+            .iter()
+            .zip(&old)
+            .filter(|(_, (from, _))| !pairs.iter().any(|x| &x.0 == from))
+            .map(|(x, _)| x.clone())
+            .collect();
+        renamed.extend(
+            pairs
+                .iter()
+                .filter(|(_, to)| to.exists())
+                .map(|(from, to)| Rename::new(base, from, to)),
+        );
+        Ok(Renamed {
+            records: renamed,
+            moves,
+        })
+    }
+
+    // 按记录改回原文件名, 返回没有撤销成功的记录.
+    fn undo_rename(&self, path: &MapPath) -> Result<Renamed, Box<dyn Error>> {
+        let map = self.data.get_map(path).ok_or("source not found")?;
+        let root = self.root_of(map).ok_or("root not configured")?;
+        let base = Path::new(&root.anime);
+        let moves: Vec<_> = map
+            .renamed
+            .iter()
+            .map(|x| {
+                let (from, to) = x.paths(base);
+                (to, from)
+            })
+            .collect();
+        if self.config.dry_run {
+            return Ok(Renamed {
+                records: map.renamed.clone(),
+                moves,
+            });
+        }
+        let (done, errors) = rename::rename_all(&moves, &[]);
+        for error in &errors {
+            println!("rename error: {}", error);
+        }
+        let records = map
+            .renamed
+            .iter()
+            .zip(&moves)
+            .filter(|(_, x)| !done.contains(x))
+            .map(|(x, _)| x.clone())
+            .collect();
+        Ok(Renamed { records, moves })
+    }
+
+    // 按回退链链接 map, 返回实际使用的链接方式.
+    // 单独映射的文件先从文件夹中排除, 再分别链接到各自的目标.
+    fn reflink_map(&self, path: &MapPath) -> Result<Linked, Box<dyn Error>> {
+        let (source, anime) = self
+            .map_destination(path)
+            .ok_or(format!("root not configured: {}", path.root))?;
+        let map = self.data.get_map(path).unwrap();
+        let link_modes = self.link_modes_of(map);
+        let media = &self.config.media;
+
+        println!("Source: {}, Anime: {}.", source.display(), anime.display());
+        if let Some(parent) = anime.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let filter = self.folder_filter(map, &source)?;
+        let flatten = map.layout == Layout::Flatten && map.file_type == FileType::Dir;
+        // 平铺时不覆盖动漫文件夹中的其他文件, 只替换这个 map 之前链接的和回退前链接的.
+        let replaceable = RefCell::new(match map.linked_by {
+            Some(_) => self.linked_files(path).into_iter().collect(),
+            None => HashSet::new(),
+        });
+        let result = linker::fallback(link_modes, |linker| match flatten {
+            true => {
+                let replace = |x: &Path| replaceable.borrow().contains(x);
+                let report = linker::link_flat(linker, &source, &anime, &filter, &replace);
+                replaceable
+                    .borrow_mut()
+                    .extend(report.targets.iter().cloned());
+                report
             }
-            _ => {
-                let err = format!(
-                    "Only support linux system. Source: {}, Anime: {}.",
-                    source, anime
-                );
-                Err(err.into())
+            false => linker::link_to(linker, &source, &anime, &filter),
+        });
+        let mode = Self::link_result(result, &source, &anime)?;
+        println!("Linked by {}.", mode);
+
+        let mut files = Vec::new();
+        for file in map.files.iter().filter(|x| x.include) {
+            let Some((source, destination)) = self.file_destination(path, file) else {
+                files.push((file.source.clone(), mode));
+                continue;
+            };
+            println!("File: {}, To: {}.", source.display(), destination.display());
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
             }
+            // 单独列出的文件即使类别不需要链接也要链接.
+            let filter = |path: &Path| path == source || media.should_link(path);
+            let result = linker::link_to_with_fallback(link_modes, &source, &destination, &filter);
+            files.push((
+                file.source.clone(),
+                Self::link_result(result, &source, &destination)?,
+            ));
         }
+        Ok(Linked {
+            path: path.clone(),
+            mode,
+            files,
+        })
     }
+
+    fn link_result(
+        result: Result<(LinkMode, linker::Report), Vec<(LinkMode, linker::Report)>>,
+        source: &Path,
+        target: &Path,
+    ) -> Result<LinkMode, Box<dyn Error>> {
+        // 成功时也逐个输出警告.
+        if let Ok((mode, report)) = &result {
+            for warning in &report.warnings {
+                println!("{} warning: {}", mode, warning);
+            }
+        }
+        result.map(|x| x.0).map_err(|failed| {
+            // 逐个输出失败的文件.
+            for (mode, report) in &failed {
+                for error in &report.errors {
+                    println!("{} error: {}", mode, error);
+                }
+            }
+            format!(
+                "All link modes failed. Source: {}, Anime: {}.",
+                source.display(),
+                target.display()
+            )
+            .into()
+        })
+    }
+}
+
+// 一个 map 的重命名结果.
+struct Renamed {
+    records: Vec<Rename>,           // 新的重命名记录.
+    moves: Vec<(PathBuf, PathBuf)>, // 需要执行的重命名, 只读运行时没有执行.
+}
+
+// 一个 map 的链接结果.
+struct Linked {
+    path: MapPath,
+    mode: LinkMode,
+    files: Vec<(String, LinkMode)>, // 单独映射的文件实际使用的链接方式.
 }
 
-#[derive(Serialize, Deserialize, Default)]
+// 保存的结构都不接受未知字段, 拼错的字段报错而不是被丢掉.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct RealData {
+    #[serde(default)]
+    pub version: u32, // map 文件的版本, 见 migrate.
     pub source_anime_maps: Vec<SourceAnimeMap>, // 文件夹映射.
-    pub animes: Vec<String>,                    // 动漫列表.
+    #[serde(default)]
+    pub animes: Vec<String>, // 动漫列表.
+}
+
+impl Default for RealData {
+    fn default() -> Self {
+        RealData {
+            version: migrate::CURRENT_VERSION,
+            source_anime_maps: Vec::new(),
+            animes: Vec::new(),
+        }
+    }
 }
 
 impl RealData {
-    // 从文件中读取数据.
-    fn from_file(file: File) -> RealData {
-        let mut real_data = Self::default();
-        // serde_yaml::from_reader::<&File, Data> 指定泛型类型.
-        if let Ok(exist_data) = serde_yaml::from_reader::<&File, RealData>(&file) {
-            let maps = exist_data.source_anime_maps;
-            if !maps.is_empty() {
-                real_data.source_anime_maps.extend(maps);
-            }
+    // 从文件内容中读取数据, 旧版本的文件会先迁移到当前版本.
+    // 解析失败时返回错误, 不能用空数据覆盖已有的 map.
+    fn from_str(content: &str) -> Result<RealData, String> {
+        if content.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+        let version = migrate::migrate(&mut value)?;
+        // 当前版本直接解析文本, 错误信息才有行号.
+        let exist_data: RealData = match version == migrate::CURRENT_VERSION {
+            true => serde_yaml::from_str(content),
+            false => serde_yaml::from_value(value),
         }
-        real_data
+        .map_err(|e| e.to_string())?;
+        // 动漫列表每次都重新扫描.
+        Ok(RealData {
+            source_anime_maps: exist_data.source_anime_maps,
+            ..Self::default()
+        })
     }
 
     // 添加文件夹映射.
-    pub fn push_new_map(&mut self, name: String, file_type: FileType) {
-        let anime_map = bulid_anime_map(name, "".to_string(), file_type);
-        self.source_anime_maps.push(anime_map);
+    pub fn push_new_map(&mut self, map: SourceAnimeMap) {
+        self.source_anime_maps.push(map);
     }
 
     // 更新文件夹映射.
-    pub fn push_renew_map(&mut self, name: String, file_type: FileType) {
-        let anime_map = self.source_anime_maps.iter_mut().find(|x| x.source == name);
+    pub fn push_renew_map(&mut self, map: SourceAnimeMap) {
+        let anime_map = self
+            .source_anime_maps
+            .iter_mut()
+            .find(|x| x.root == map.root && x.source == map.source);
         let anime_map = anime_map.unwrap();
-        let file_type: FileType = match &file_type {
-            FileType::Nesting(x) => {
-                // 嵌套文件夹要继承父文件夹对应的 anime.
-                // 这里所有权复杂，直接 clone 后修改.
-                let y = x
-                    .iter()
-                    .map(|y| {
-                        let mut z = y.clone();
-                        z.anime = anime_map.anime.clone();
-                        z
-                    })
-                    .collect();
-                FileType::Nesting(y)
+        anime_map.ignored_by = map.ignored_by;
+        // 嵌套文件夹的每一层都要继承父文件夹对应的 anime.
+        fn inherit(file_type: &mut FileType, anime: &str) {
+            if let FileType::Nesting(maps) = file_type {
+                maps.iter_mut().for_each(|x| {
+                    x.anime = anime.to_string();
+                    inherit(&mut x.file_type, anime);
+                });
             }
-            _ => file_type,
-        };
+        }
+        let mut file_type = map.file_type;
+        inherit(&mut file_type, &anime_map.anime);
         if anime_map.file_type != file_type {
             anime_map.file_type = file_type;
         }
     }
 
+    // 把 root 为 from 的 map 改为 to, 嵌套的 map 也一起修改.
+    fn rename_root(&mut self, from: &str, to: &str) {
+        fn rename(maps: &mut [SourceAnimeMap], from: &str, to: &str) {
+            for map in maps.iter_mut() {
+                if map.root == from {
+                    map.root = to.to_string();
+                }
+                if let FileType::Nesting(maps) = &mut map.file_type {
+                    rename(maps, from, to);
+                }
+            }
+        }
+        rename(&mut self.source_anime_maps, from, to);
+    }
+
     pub fn push_anime(&mut self, name: String) {
         if !self.animes.contains(&name) {
             self.animes.push(name)
         }
     }
 
-    // 获取 map，可以嵌套.
-    fn get_map_at_indexes(&self, i: (usize, usize)) -> &SourceAnimeMap {
-        match &self.source_anime_maps[i.0].file_type {
-            FileType::Nesting(maps) => &maps[i.1],
-            _ => &self.source_anime_maps[i.0],
-        }
+    // 按 "Collection/Series/Season" 形式的源路径查找 map 的地址.
+    // root 为 None 时使用第一个匹配的顶层 map.
+    fn find_map(&self, root: Option<&str>, source: &str) -> Result<MapPath, String> {
+        let path = MapPath::new("", source);
+        let top = self
+            .source_anime_maps
+            .iter()
+            .find(|x| x.source == path.sources[0] && root.is_none_or(|root| x.root == root))
+            .ok_or(format!("source not found: {}", path.sources[0]))?;
+        top.get(&path.sources[1..])
+            .ok_or(format!("source not found: {}", source))?;
+        Ok(MapPath {
+            root: top.root.clone(),
+            ..path
+        })
+    }
+
+    // 获取 map, 可以任意层嵌套.
+    fn get_map(&self, path: &MapPath) -> Option<&SourceAnimeMap> {
+        let (first, rest) = path.sources.split_first()?;
+        self.source_anime_maps
+            .iter()
+            .find(|x| x.root == path.root && &x.source == first)?
+            .get(rest)
+    }
+
+    // 地址所在的顶层 map.
+    fn top_map_mut(&mut self, path: &MapPath) -> Result<&mut SourceAnimeMap, String> {
+        let first = path.sources.first().ok_or("empty source")?;
+        self.source_anime_maps
+            .iter_mut()
+            .find(|x| x.root == path.root && &x.source == first)
+            .ok_or(format!("source not found: {}", path))
+    }
+
+    // 设置地址处的 map 的 anime name.
+    fn set_anime_name(&mut self, reflink_queue: &[(MapPath, String)]) {
+        reflink_queue.iter().for_each(|(path, anime)| {
+            self.top_map_mut(path)
+                .and_then(|x| x.set_anime(&path.sources[1..], anime))
+                .unwrap();
+        });
     }
 
-    // 设置索引处的 map 的 anime name.
-    fn set_anime_name(&mut self, reflink_queue: &[(usize, usize, String)]) {
-        reflink_queue.iter().for_each(|i| {
-            self.source_anime_maps[i.0]
-                .set_anime(Value::Index((i.1, &i.2)))
+    fn set_map_active(&mut self, successed: &[(MapPath, bool)]) {
+        successed.iter().for_each(|(path, active)| {
+            self.top_map_mut(path)
+                .and_then(|x| x.set_active(&path.sources[1..], *active))
                 .unwrap();
         });
     }
 
-    fn set_map_active(&mut self, successed_index: &[(usize, usize, bool)]) {
-        successed_index.iter().for_each(|i| {
-            self.source_anime_maps[i.0]
-                .set_active(Value::Index((i.1, i.2)))
+    // 记录 map 实际使用的链接方式.
+    fn set_map_linked_by(&mut self, linked: &[(MapPath, LinkMode)]) {
+        linked.iter().for_each(|(path, mode)| {
+            self.top_map_mut(path)
+                .and_then(|x| x.set_linked_by(&path.sources[1..], *mode))
                 .unwrap();
         });
     }
+
+    fn set_map_renamed(&mut self, path: &MapPath, renamed: Vec<Rename>) {
+        if let Some(map) = self
+            .top_map_mut(path)
+            .ok()
+            .and_then(|x| x.get_mut(&path.sources[1..]))
+        {
+            map.renamed = renamed;
+        }
+    }
+
+    // 记录单独映射的文件实际使用的链接方式.
+    fn set_files_linked_by(&mut self, path: &MapPath, files: &[(String, LinkMode)]) {
+        let Some(map) = self
+            .top_map_mut(path)
+            .ok()
+            .and_then(|x| x.get_mut(&path.sources[1..]))
+        else {
+            return;
+        };
+        for (source, mode) in files {
+            map.file_mut(source).linked_by = Some(*mode);
+        }
+    }
+}
+
+// 已链接的 map 的地址, 嵌套的 map 递归获取.
+fn linked_paths(maps: &[SourceAnimeMap], parent: Option<&MapPath>) -> Vec<MapPath> {
+    let mut paths = Vec::new();
+    for map in maps {
+        let path = match parent {
+            Some(parent) => parent.child(&map.source),
+            None => MapPath::new(&map.root, &map.source),
+        };
+        match &map.file_type {
+            FileType::Nesting(maps) => paths.extend(linked_paths(maps, Some(&path))),
+            _ if map.linked_by.is_some() => paths.push(path),
+            _ => {}
+        }
+    }
+    paths
+}
+
+// 递归获取文件夹下的所有文件, 不进入软链接的文件夹.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        match entry.file_type() {
+            Ok(x) if x.is_dir() => collect_files(&entry.path(), files),
+            Ok(_) => files.push(entry.path()),
+            Err(_) => {}
+        }
+    }
 }
 
 // 构建文件夹映射
-fn bulid_anime_map(source: String, anime: String, file_type: FileType) -> SourceAnimeMap {
+fn bulid_anime_map(
+    root: &str,
+    source: String,
+    anime: String,
+    file_type: FileType,
+) -> SourceAnimeMap {
     SourceAnimeMap {
         source,
+        root: root.to_string(),
         anime,
         active: true,
         file_type,
+        ..Default::default()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
     use tempfile::*;
 
     fn get_real_data() -> RealData {
@@ -460,12 +1394,14 @@ mod tests {
                     anime: "file_anime".to_string(),
                     active: true,
                     file_type: FileType::File,
+                    ..Default::default()
                 },
                 SourceAnimeMap {
                     source: "dir_source".to_string(),
                     anime: "dir_anime".to_string(),
                     active: true,
                     file_type: FileType::Dir,
+                    ..Default::default()
                 },
                 SourceAnimeMap {
                     source: "nesting_source".to_string(),
@@ -477,17 +1413,20 @@ mod tests {
                             anime: "nesting_file_anime".to_string(),
                             active: true,
                             file_type: FileType::File,
+                            ..Default::default()
                         },
                         SourceAnimeMap {
                             source: "nesting_dir_source".to_string(),
                             anime: "nesting_dir_anime".to_string(),
                             active: true,
                             file_type: FileType::Dir,
+                            ..Default::default()
                         },
                     ]),
+                    ..Default::default()
                 },
             ],
-            animes: Vec::new(),
+            ..Default::default()
         }
     }
 
@@ -527,11 +1466,125 @@ mod tests {
         use super::*;
 
         fn create_data() -> Data {
+            crate::config::isolate_env();
             Data {
                 data: get_real_data(),
                 source_map: HashMap::new(),
-                config: Config::new([].into_iter()),
+                anime_dirs: HashMap::new(),
+                reviews: Vec::new(),
+                renames: Vec::new(),
+                config: Config::new(
+                    ["", "plan", "-s", "./SOURCE", "-a", "./ANIME"]
+                        .map(String::from)
+                        .into_iter(),
+                )
+                .unwrap(),
+            }
+        }
+
+        #[test]
+        fn edit_map() {
+            let mut data = create_data();
+            let config = Config::new(
+                [
+                    "",
+                    "edit",
+                    "nesting_source/nesting_dir_source",
+                    "--anime",
+                    "AIR",
+                    "--active",
+                    "false",
+                ]
+                .map(String::from)
+                .into_iter(),
+            )
+            .unwrap();
+            let Action::Edit(edit) = config.action else {
+                panic!("")
+            };
+            data.edit_map(&edit).unwrap();
+            let FileType::Nesting(nesting) = &data.data.source_anime_maps[2].file_type else {
+                panic!("")
+            };
+            assert_eq!(nesting[1].anime, "AIR");
+            assert!(!nesting[1].active);
+            assert_eq!(data.data.source_anime_maps[2].anime, "nesting_anime, AIR");
+
+            let mut edit = edit;
+            edit.source = "file_source/child".to_string();
+            assert!(data.edit_map(&edit).is_err());
+            edit.source = "not_exist".to_string();
+            assert!(data.edit_map(&edit).is_err());
+        }
+
+        #[test]
+        fn push_map_from_dir() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let mut data = create_data();
+            data.data = RealData::default();
+            let root = |name: &str| {
+                let path = tep_dir.path().join(name);
+                fs::create_dir_all(path.join("source")).unwrap();
+                fs::write(path.join("a.torrent"), b"").unwrap();
+                fs::write(path.join(IGNORE_FILE), b"music/\n").unwrap();
+                fs::create_dir(path.join("music")).unwrap();
+                path.to_str().unwrap().to_string()
+            };
+            let anime_path = tep_dir.path().join("anime").to_str().unwrap().to_string();
+            data.config.roots = vec![
+                Root::new(root("tv"), anime_path.clone()),
+                Root::new(root("movie"), anime_path),
+            ];
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+
+            // 同名的源文件夹分别属于不同的组合, 动漫列表只有一份.
+            // 被忽略的 map 记录匹配的规则, 忽略文件本身不记录.
+            let maps: Vec<_> = data
+                .data
+                .source_anime_maps
+                .iter()
+                .filter(|x| x.source == "source")
+                .collect();
+            assert_eq!(maps.len(), 2);
+            assert_ne!(maps[0].root, maps[1].root);
+            let ignored: Vec<_> = data
+                .data
+                .source_anime_maps
+                .iter()
+                .filter(|x| x.file_type.is_other() && x.root == maps[0].root)
+                .map(|x| (x.source.as_str(), x.ignored_by.as_deref().unwrap()))
+                .collect();
+            assert_eq!(ignored.len(), 2, "{:?}", ignored);
+            assert!(ignored.contains(&("a.torrent", "config: *.torrent")));
+            assert!(ignored.contains(&("music", ".reflinkignore:1: music/")));
+            assert_eq!(data.data.source_anime_maps.len(), 6);
+            assert_eq!(data.data.animes.len(), 4);
+
+            // 同时读取的结果和单线程相同.
+            let parallel = data.data.source_anime_maps.clone();
+            data.config.jobs = 1;
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            assert_eq!(data.data.source_anime_maps, parallel);
+
+            // 名称不是 UTF-8 的源跳过, 不存在的文件夹报错.
+            #[cfg(unix)]
+            {
+                use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+                let tv = tep_dir.path().join("tv");
+                fs::create_dir(tv.join(OsStr::from_bytes(b"bad\xff"))).unwrap();
+                data.data = RealData::default();
+                data.push_map_from_dir().unwrap();
+                assert_eq!(data.data.source_anime_maps, parallel);
             }
+            data.config.roots.push(Root::new(
+                tep_dir.path().join("missing").to_str().unwrap().to_string(),
+                tep_dir.path().join("anime").to_str().unwrap().to_string(),
+            ));
+            let err = data.push_map_from_dir().unwrap_err();
+            assert!(err.to_string().contains("missing"), "{}", err);
         }
 
         #[test]
@@ -539,9 +1592,12 @@ mod tests {
             let tep_dir = tempdir_in("./").unwrap();
             temp_anime_dir(&tep_dir).unwrap();
             let mut data = create_data();
-            data.config.anime_path = tep_dir.path().join("anime").to_str().unwrap().to_string();
-            let mut anime_cache = Cache::default();
-            let set = data.fetch_anime_cache(ANIME_4, &mut anime_cache);
+            let anime_path = tep_dir.path().join("anime").to_str().unwrap().to_string();
+            data.config.roots = vec![Root::new("./SOURCE".to_string(), anime_path)];
+            data.push_anime_from_dir().unwrap();
+            let mut state = MatchState::default();
+            data.fetch_anime_caches(&[ANIME_4.to_string()], &mut state);
+            let set = state.anime_cache.get(ANIME_4).unwrap();
             assert_eq!(
                 set,
                 &Cache::from([
@@ -552,8 +1608,9 @@ mod tests {
                 "set is {:?}",
                 set
             );
+            assert_eq!(state.names.owners(ANIME_4_SUB[3]), [ANIME_4]);
             assert_eq!(
-                anime_cache,
+                state.anime_cache,
                 Cache::from([(
                     ANIME_4,
                     Cache::from([
@@ -564,33 +1621,581 @@ mod tests {
                 )])
             );
         }
+
+        #[test]
+        fn deep_nesting() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            let season = source_path.join("Collection").join("AIR").join("Season 01");
+            fs::create_dir_all(&season).unwrap();
+            fs::create_dir_all(source_path.join("Collection").join("Movie")).unwrap();
+            fs::write(season.join("a.mkv"), b"a").unwrap();
+            fs::write(source_path.join("Collection/Movie/b.mkv"), b"b").unwrap();
+            let mut data = create_data();
+            let anime_path = tep_dir.path().join("anime").to_str().unwrap().to_string();
+            let source_path = source_path.to_str().unwrap().to_string();
+            data.config.roots = vec![Root::new(source_path.clone(), anime_path)];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+
+            // 每一层只有文件夹时都是嵌套的, 叶子可以单独设置动漫.
+            let leaf = MapPath::new("source", "Collection/AIR/Season 01");
+            assert_eq!(data.data.get_map(&leaf).unwrap().file_type, FileType::Dir);
+            let edit = |source: &str| EditArgs {
+                source: source.to_string(),
+                root: None,
+                anime: Some(ANIME_1.to_string()),
+                active: None,
+                link_mode: None,
+                sub_path: None,
+                layout: None,
+                season: None,
+                rename: None,
+                file: None,
+                destination: None,
+                include: None,
+            };
+            data.edit_map(&edit(&leaf.to_string())).unwrap();
+            let tasks = data.plan_animes();
+            assert_eq!(tasks.len(), 1);
+            assert_eq!(tasks[0].path, leaf);
+            let (source, destination) = data.map_destination(&leaf).unwrap();
+            assert_eq!(
+                source,
+                Path::new(&source_path).join("Collection/AIR/Season 01")
+            );
+            assert!(destination.ends_with(Path::new(ANIME_1).join("Season 01")));
+            let collection = data.data.get_map(&MapPath::new("source", "Collection"));
+            assert_eq!(collection.unwrap().anime, ANIME_1);
+        }
+
+        #[test]
+        fn apply_plan() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            fs::create_dir_all(source_path.join("air_source")).unwrap();
+            fs::write(source_path.join("air_source").join("a.mkv"), b"a").unwrap();
+            let mut data = create_data();
+            let source_path = source_path.to_str().unwrap().to_string();
+            let anime_path = tep_dir.path().join("anime").to_str().unwrap().to_string();
+            data.config.roots = vec![Root::new(source_path.clone(), anime_path.clone())];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+            data.data.source_anime_maps[0].anime = ANIME_1.to_string();
+
+            let tasks = data.plan_animes();
+            let plan = data.plan_file(&tasks).unwrap();
+            assert_eq!(plan.tasks[0].task.path.to_string(), "air_source");
+            assert_eq!(plan.tasks[0].source_state.files.len(), 1);
+
+            // 计划后新增的源不影响执行.
+            fs::create_dir(Path::new(&source_path).join("new_source")).unwrap();
+            data.data
+                .source_anime_maps
+                .insert(0, SourceAnimeMap::default());
+            let applied = data.apply_plan(&plan).unwrap();
+            assert_eq!(applied[0].path, plan.tasks[0].task.path);
+
+            // 计划后修改了 map 的设置.
+            let path = tep_dir.path().join("plan.yaml");
+            plan.write(&path).unwrap();
+            let plan = PlanFile::from_path(&path).unwrap();
+            let map = data.data.get_map(&plan.tasks[0].task.path).unwrap().clone();
+            data.data.source_anime_maps[1].season = Some(2);
+            let err = data.apply_plan(&plan).unwrap_err();
+            assert!(err.to_string().contains("season"), "{}", err);
+            data.data.source_anime_maps[1] = map;
+            assert_eq!(
+                Path::new(&plan.tasks[0].map.destination),
+                Path::new(ANIME_1).join("air_source")
+            );
+
+            // 在挂载位置不同的机器上执行.
+            let nas = tep_dir.path().join("nas");
+            fs::create_dir(&nas).unwrap();
+            fs::rename(&source_path, nas.join("source")).unwrap();
+            fs::rename(&anime_path, nas.join("anime")).unwrap();
+            let mut remote = create_data();
+            remote.config.roots = vec![Root::new(
+                nas.join("source").to_str().unwrap().to_string(),
+                nas.join("anime").to_str().unwrap().to_string(),
+            )];
+            remote.data = data.data.clone();
+            remote.push_anime_from_dir().unwrap();
+            remote.apply_plan(&plan).unwrap();
+
+            fs::write(nas.join("source").join("air_source").join("a.mkv"), b"aa").unwrap();
+            let err = remote.apply_plan(&plan).unwrap_err();
+            assert!(err.to_string().contains("a.mkv"), "{}", err);
+        }
+
+        #[test]
+        fn from_yaml() {
+            let tep_dir = tempdir_in("./").unwrap();
+            let mut data = create_data();
+            let map_path = tep_dir.path().join("data.yaml");
+            data.config.mapfile_path = map_path.to_str().unwrap().to_string();
+            data.data.source_anime_maps[0].root = data.config.roots[0].source.clone();
+            data.data.source_anime_maps[1].root = String::new();
+            data.write_yaml().unwrap();
+
+            // 旧版本用源文件夹地址作为名称, 没有 root 的归入第一组.
+            let data = Data::from_yaml(data.config).unwrap();
+            let roots: Vec<_> = data.data.source_anime_maps[..2]
+                .iter()
+                .map(|x| x.root.as_str())
+                .collect();
+            assert_eq!(roots, ["SOURCE", "SOURCE"]);
+        }
+
+        #[test]
+        fn title_match() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            for (i, name) in [
+                "[SweetSub] Ａｉｒ S2 [WebRip][1080P]",
+                "[Nekomoe] Just Becuase! [1080p]",
+                "[VCB-Studio] Air Gear [1080p]",
+            ]
+            .iter()
+            .enumerate()
+            {
+                fs::create_dir_all(source_path.join(name)).unwrap();
+                fs::write(source_path.join(name).join(format!("{}.mkv", i)), b"").unwrap();
+            }
+            let mut data = create_data();
+            data.config.roots = vec![Root::new(
+                source_path.to_str().unwrap().to_string(),
+                tep_dir.path().join("anime").to_str().unwrap().to_string(),
+            )];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+
+            let mut tasks: Vec<_> = data
+                .plan_animes()
+                .into_iter()
+                .map(|x| (x.anime, x.reason))
+                .collect();
+            tasks.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(tasks.len(), 2, "{:?}", tasks);
+            let air = MatchReason::Title {
+                title: "air".to_string(),
+                score: 1.0,
+            };
+            assert_eq!(tasks[0], (ANIME_1.to_string(), air));
+            assert_eq!(tasks[1].0, ANIME_2);
+            assert_eq!(
+                tasks[1].1.to_string(),
+                "title similar to just because (score 0.83)"
+            );
+
+            // 提高阈值后只有完全相同的标题.
+            data.config.match_threshold = 1.0;
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+            assert_eq!(data.plan_animes().len(), 1);
+        }
+
+        #[test]
+        fn same_content() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let anime_path = tep_dir.path().join("anime");
+            let content = vec![1u8; 2 * 1024 * 1024];
+            fs::write(anime_path.join(ANIME_3).join("S01E01.mkv"), &content).unwrap();
+            // 大小不同的文件不计算指纹.
+            fs::write(
+                anime_path.join(ANIME_1).join("S01E02.mkv"),
+                vec![1u8; 3 * 1024 * 1024],
+            )
+            .unwrap();
+            let source_path = tep_dir.path().join("source");
+            let source = source_path.join("[Group] Shinjitsu no Namida [1080p]");
+            fs::create_dir_all(&source).unwrap();
+            fs::write(source.join("[Group] 01 [1080p].mkv"), &content).unwrap();
+            let mut data = create_data();
+            data.config.roots = vec![Root::new(
+                source_path.to_str().unwrap().to_string(),
+                anime_path.to_str().unwrap().to_string(),
+            )];
+            let map_path = tep_dir.path().join("data.yaml");
+            data.config.mapfile_path = map_path.to_str().unwrap().to_string();
+            data.config.dry_run = false;
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+
+            // 文件名不同但内容相同.
+            let tasks = data.plan_animes();
+            assert_eq!(tasks.len(), 1);
+            assert_eq!(tasks[0].anime, ANIME_3);
+            assert_eq!(
+                tasks[0].reason,
+                MatchReason::SameContent {
+                    files: vec!["[Group] 01 [1080p].mkv".to_string()]
+                }
+            );
+            let fingerprints = fs::read_to_string(FingerprintCache::path(&map_path)).unwrap();
+            assert!(fingerprints.contains("S01E01.mkv"));
+            assert!(!fingerprints.contains("S01E02.mkv"));
+        }
+
+        #[test]
+        fn needs_review() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let anime_path = tep_dir.path().join("anime");
+            fs::write(anime_path.join(ANIME_1).join("NCOP.mkv"), b"").unwrap();
+            fs::write(anime_path.join(ANIME_3).join("NCOP.mkv"), b"").unwrap();
+            let source_path = tep_dir.path().join("source");
+            fs::create_dir_all(source_path.join("Extras")).unwrap();
+            fs::write(source_path.join("Extras").join("NCOP.mkv"), b"").unwrap();
+            let mut data = create_data();
+            data.config.roots = vec![Root::new(
+                source_path.to_str().unwrap().to_string(),
+                anime_path.to_str().unwrap().to_string(),
+            )];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+            let before = data.data.clone();
+
+            // 只有共有的文件名时不猜测.
+            let tasks = data.plan_animes();
+            assert!(tasks.is_empty());
+            let plan = data.build_plan(&before, &tasks);
+            assert_eq!(plan.reviews.len(), 1);
+            assert_eq!(plan.reviews[0].source, "Extras");
+            let animes: Vec<_> = plan.reviews[0]
+                .candidates
+                .iter()
+                .map(|x| x.anime.as_str())
+                .collect();
+            assert_eq!(animes, [ANIME_1, ANIME_3]);
+            assert!(plan.to_string().contains("Needs review (1):"));
+            assert!(data.data.source_anime_maps[0].anime.is_empty());
+
+            // 真正链接时也要列出, 并且不链接.
+            data.config.action = Action::Link;
+            data.config.dry_run = false;
+            data.config.mapfile_path = tep_dir.path().join("data.yaml").to_str().unwrap().into();
+            assert!(data.map_animes().unwrap().is_empty());
+            assert_eq!(data.reviews().len(), 1);
+            let review = data.reviews()[0].to_string();
+            assert!(review.contains("Extras"), "{}", review);
+            assert!(
+                review.contains(ANIME_1) && review.contains(ANIME_3),
+                "{}",
+                review
+            );
+            assert!(data.data.source_anime_maps[0].active);
+        }
+
+        #[test]
+        fn layout() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            let air_source = source_path.join("[VCB-Studio] AIR S2 [Ma10p_1080p]");
+            fs::create_dir_all(air_source.join("SPs")).unwrap();
+            fs::write(air_source.join("a.mkv"), b"a").unwrap();
+            fs::write(air_source.join("SPs").join("b.mkv"), b"b").unwrap();
+            let mut data = create_data();
+            let anime_path = tep_dir.path().join("anime");
+            data.config.roots = vec![Root::new(
+                source_path.to_str().unwrap().to_string(),
+                anime_path.to_str().unwrap().to_string(),
+            )];
+            data.config.link_modes = vec![LinkMode::Copy];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+
+            let mut edit = EditArgs {
+                source: "[VCB-Studio] AIR S2 [Ma10p_1080p]".to_string(),
+                root: None,
+                anime: Some(ANIME_1.to_string()),
+                active: None,
+                link_mode: None,
+                sub_path: None,
+                layout: Some(Layout::Season),
+                season: None,
+                rename: None,
+                file: None,
+                destination: None,
+                include: None,
+            };
+            data.edit_map(&edit).unwrap();
+            let tasks = data.plan_animes();
+            let season = anime_path.join(ANIME_1).join("Season 02");
+            let map = &data.data.source_anime_maps[0];
+            assert_eq!(
+                Path::new(&map.destination),
+                Path::new(ANIME_1).join("Season 02")
+            );
+            data.link_tasks(&tasks);
+            assert!(season.join("a.mkv").exists());
+            assert!(season.join("SPs").join("b.mkv").exists());
+
+            // 平铺时子文件夹中的文件也直接放到子路径下.
+            edit.layout = Some(Layout::Flatten);
+            edit.sub_path = Some("Specials".to_string());
+            data.edit_map(&edit).unwrap();
+            data.data.source_anime_maps[0].active = true;
+            let tasks = data.plan_animes();
+            let plan = data.build_plan(&RealData::default(), &tasks);
+            let specials = anime_path.join(ANIME_1).join("Specials");
+            assert_eq!(plan.operations[0].destination, specials);
+            data.link_tasks(&tasks);
+            assert!(specials.join("a.mkv").exists());
+            assert!(specials.join("b.mkv").exists());
+        }
+
+        #[test]
+        fn rename_maps() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            let air_source = source_path.join("[VCB-Studio] AIR [Ma10p_1080p]");
+            fs::create_dir_all(&air_source).unwrap();
+            for name in [
+                "[VCB-Studio] AIR [01][Ma10p_1080p].mkv",
+                "[VCB-Studio] AIR [01][Ma10p_1080p].sc.ass",
+                "[VCB-Studio] AIR [Menu][Ma10p_1080p].mkv",
+            ] {
+                fs::write(air_source.join(name), name).unwrap();
+            }
+            let mut data = create_data();
+            let anime_path = tep_dir.path().join("anime");
+            data.config.roots = vec![Root::new(
+                source_path.to_str().unwrap().to_string(),
+                anime_path.to_str().unwrap().to_string(),
+            )];
+            data.config.link_modes = vec![LinkMode::Copy];
+            data.config.rename =
+                Some(Template::new("{anime} - S{season:02}E{episode:02}{ext}").unwrap());
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+            data.data.source_anime_maps[0].anime = ANIME_1.to_string();
+            data.data.source_anime_maps[0].layout = Layout::Flatten;
+            data.config.dry_run = false;
+
+            let tasks = data.plan_animes();
+            data.link_tasks(&tasks);
+            let dir = anime_path.join(ANIME_1);
+            let names = || {
+                let mut names: Vec<_> = fs::read_dir(&dir)
+                    .unwrap()
+                    .flatten()
+                    .map(|x| x.file_name().into_string().unwrap())
+                    .collect();
+                names.sort();
+                names
+            };
+            // 识别不到集数的文件保持原样.
+            let renamed = [
+                format!("{} - S01E01.mkv", ANIME_1),
+                format!("{} - S01E01.sc.ass", ANIME_1),
+                "[VCB-Studio] AIR [Menu][Ma10p_1080p].mkv".to_string(),
+            ];
+            assert_eq!(names(), renamed);
+            assert_eq!(data.data.source_anime_maps[0].renamed.len(), 2);
+
+            // 换了模板可以重复执行, 记录的原文件名不变.
+            let args = |undo| RenameArgs {
+                source: None,
+                root: None,
+                undo,
+            };
+            data.config.rename = Some(Template::new("AIR E{episode}{ext}").unwrap());
+            // 只读运行时重命名只出现在计划中.
+            data.config.dry_run = true;
+            data.rename_maps(&args(false)).unwrap();
+            assert_eq!(names(), renamed);
+            let plan = data.build_plan(&data.data.clone(), &[]);
+            assert_eq!(plan.renames.len(), 2);
+            assert_eq!(plan.renames[0].to, dir.join("AIR E1.mkv"));
+            assert!(plan.to_string().contains("Renames (2):"));
+            data.config.dry_run = false;
+            data.rename_maps(&args(false)).unwrap();
+            assert_eq!(names()[..2], ["AIR E1.mkv", "AIR E1.sc.ass"]);
+
+            data.rename_maps(&args(true)).unwrap();
+            assert!(dir
+                .join("[VCB-Studio] AIR [01][Ma10p_1080p].sc.ass")
+                .exists());
+            assert!(data.data.source_anime_maps[0].renamed.is_empty());
+        }
+
+        #[test]
+        fn file_maps() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            let air_source = source_path.join("air_source");
+            fs::create_dir_all(&air_source).unwrap();
+            for name in ["01.mkv", "02.mkv", "13.mkv"] {
+                fs::write(air_source.join(name), name).unwrap();
+            }
+            let mut data = create_data();
+            let source_path = source_path.to_str().unwrap().to_string();
+            let anime_path = tep_dir.path().join("anime");
+            data.config.roots = vec![Root::new(
+                source_path.clone(),
+                anime_path.to_str().unwrap().to_string(),
+            )];
+            data.config.link_modes = vec![LinkMode::Copy];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+
+            let edit = |file: &str, destination: Option<&str>, include: Option<bool>| EditArgs {
+                source: "air_source".to_string(),
+                root: None,
+                anime: Some(ANIME_1.to_string()),
+                active: None,
+                link_mode: None,
+                sub_path: None,
+                layout: None,
+                season: None,
+                rename: None,
+                file: Some(file.to_string()),
+                destination: destination.map(String::from),
+                include,
+            };
+            data.edit_map(&edit("13.mkv", None, Some(false))).unwrap();
+            data.edit_map(&edit("02.mkv", Some("AIR Movie/AIR.mkv"), None))
+                .unwrap();
+            data.edit_map(&edit("01.mkv", None, None)).unwrap();
+
+            let tasks = data.plan_animes();
+            let plan = data.build_plan(&RealData::default(), &tasks);
+            assert_eq!(plan.operations.len(), 2);
+            data.link_tasks(&tasks);
+
+            // 没有单独目标的文件跟随文件夹, 排除的文件不链接.
+            let folder = anime_path.join(ANIME_1).join("air_source");
+            assert!(folder.join("01.mkv").exists());
+            assert!(!folder.join("02.mkv").exists());
+            assert!(!folder.join("13.mkv").exists());
+            let movie = anime_path.join("AIR Movie").join("AIR.mkv");
+            assert_eq!(fs::read(movie).unwrap(), b"02.mkv");
+            let map = &data.data.source_anime_maps[0];
+            let linked: Vec<_> = map.files.iter().map(|x| x.linked_by.is_some()).collect();
+            assert_eq!(linked, [false, true, true]);
+            assert_eq!(map.files[2].linked_by, map.linked_by);
+
+            let mut dir_edit = edit("a", None, None);
+            dir_edit.source = "air_source/01.mkv".to_string();
+            assert!(data.edit_map(&dir_edit).is_err());
+        }
+
+        #[test]
+        fn nested_ignore() {
+            let tep_dir = tempdir_in("./").unwrap();
+            temp_anime_dir(&tep_dir).unwrap();
+            let source_path = tep_dir.path().join("source");
+            let air_source = source_path.join("air_source");
+            fs::create_dir_all(air_source.join("Extras")).unwrap();
+            for name in ["01.mkv", "02.mkv.!qB", "AIR.torrent", "Extras/NCOP.mkv"] {
+                fs::write(air_source.join(name), name).unwrap();
+            }
+            fs::write(source_path.join(IGNORE_FILE), "*.!qB\nair_source/Extras/\n").unwrap();
+            let mut data = create_data();
+            let anime_path = tep_dir.path().join("anime");
+            data.config.roots = vec![Root::new(
+                source_path.to_str().unwrap().to_string(),
+                anime_path.to_str().unwrap().to_string(),
+            )];
+            data.config.ignore = vec!["*.torrent".to_string()];
+            data.config.link_modes = vec![LinkMode::Copy];
+            data.data = RealData::default();
+            data.push_map_from_dir().unwrap();
+            data.push_anime_from_dir().unwrap();
+            data.data.source_anime_maps[0].anime = ANIME_1.to_string();
+
+            // 文件夹中的文件也按源文件夹的忽略规则排除.
+            let tasks = data.plan_animes();
+            data.link_tasks(&tasks);
+            let folder = anime_path.join(ANIME_1).join("air_source");
+            assert!(folder.join("01.mkv").exists());
+            assert!(!folder.join("02.mkv.!qB").exists());
+            assert!(!folder.join("AIR.torrent").exists());
+            assert!(!folder.join("Extras").join("NCOP.mkv").exists());
+        }
     }
 
     mod read_data_tests {
         use super::*;
 
         #[test]
-        fn get_map_at_indexes() {
+        fn from_str() {
+            // 没有版本的旧文件.
+            let real_data = RealData::from_str(
+                "source_anime_maps:\n- source: a\n  anime: ''\n  active: true\n  file_type: File\n",
+            )
+            .unwrap();
+            assert_eq!(real_data.version, migrate::CURRENT_VERSION);
+            assert_eq!(real_data.source_anime_maps[0].source, "a");
+
+            let content = serde_yaml::to_string(&get_real_data()).unwrap();
+            let real_data = RealData::from_str(&content).unwrap();
+            assert_eq!(
+                real_data.source_anime_maps,
+                get_real_data().source_anime_maps
+            );
+            assert_eq!(RealData::from_str("").unwrap().source_anime_maps, []);
+
+            // 拼错的字段要报出位置, 不能当作空文件.
+            let err = RealData::from_str(&content.replace("active: true", "active: ture"))
+                .err()
+                .unwrap();
+            assert!(err.contains("line 5 column"), "{}", err);
+
+            // 拼错的可选字段不能被丢掉.
+            let typo = content.replacen("active: true", "active: true\n  link_mode: [copy]", 1);
+            let err = RealData::from_str(&typo).err().unwrap();
+            assert!(err.contains("unknown field `link_mode`"), "{}", err);
+        }
+
+        #[test]
+        fn get_map() {
             let real_data = get_real_data();
             assert_eq!(
-                real_data.get_map_at_indexes((0, 0)),
-                &real_data.source_anime_maps[0]
+                real_data.get_map(&MapPath::new("", "file_source")),
+                Some(&real_data.source_anime_maps[0])
             );
             let FileType::Nesting(nesting) = &real_data.source_anime_maps[2].file_type else {
                 panic!("")
             };
-            assert_eq!(real_data.get_map_at_indexes((2, 1)), &nesting[1]);
+            let path = MapPath::new("", "nesting_source/nesting_dir_source");
+            assert_eq!(real_data.get_map(&path), Some(&nesting[1]));
+            assert_eq!(real_data.find_map(None, &path.to_string()), Ok(path));
+            assert!(real_data.find_map(Some("tv"), "file_source").is_err());
+            assert!(real_data.find_map(None, "file_source/child").is_err());
         }
 
         #[test]
         fn set_anime_name() {
             let mut real_data = get_real_data();
-            real_data.set_anime_name(&[(0, 0, "new_anime".to_string())]);
+            let path = |source: &str| MapPath::new("", source);
+            real_data.set_anime_name(&[(path("file_source"), "new_anime".to_string())]);
             assert_eq!(
                 real_data.source_anime_maps[0].anime,
                 "new_anime".to_string()
             );
-            real_data.set_anime_name(&[(2, 1, "new_nesting_anime".to_string())]);
+            real_data.set_anime_name(&[(
+                path("nesting_source/nesting_dir_source"),
+                "new_nesting_anime".to_string(),
+            )]);
             let FileType::Nesting(nesting) = &real_data.source_anime_maps[2].file_type else {
                 panic!("")
             };
@@ -601,16 +2206,17 @@ mod tests {
         fn set_map_active() {
             let mut real_data = get_real_data();
 
-            real_data.set_map_active(&[(0, 0, false)]);
+            let path = |source: &str| MapPath::new("", source);
+            real_data.set_map_active(&[(path("file_source"), false)]);
             assert!(!real_data.source_anime_maps[0].active);
 
-            real_data.set_map_active(&[(2, 1, false)]);
+            real_data.set_map_active(&[(path("nesting_source/nesting_dir_source"), false)]);
             let FileType::Nesting(nesting) = &real_data.source_anime_maps[2].file_type else {
                 panic!("")
             };
             assert!(!nesting[1].active);
 
-            real_data.set_map_active(&[(0, 0, true)]);
+            real_data.set_map_active(&[(path("file_source"), true)]);
             assert!(real_data.source_anime_maps[0].active);
         }
     }
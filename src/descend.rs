@@ -0,0 +1,138 @@
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use serde::Deserialize;
+
+const DEFAULT_MAX_DEPTH: usize = 3;
+const DEFAULT_EXTRAS: [&str; 10] = [
+    "SPs", "SP", "CDs", "Scans", "PVs", "CMs", "Menus", "Extras", "Bonus", "特典",
+];
+
+// 文件夹名称的匹配规则, "re:" 开头的是正则表达式, 其他的是不区分大小写的 glob.
+#[derive(Debug, Clone)]
+pub enum NamePattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn new(pattern: &str) -> Result<NamePattern, String> {
+        match pattern.strip_prefix("re:") {
+            Some(regex) => Regex::new(regex)
+                .map(NamePattern::Regex)
+                .map_err(|e| format!("invalid regex {}: {}", pattern, e)),
+            None => GlobBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map(|x| NamePattern::Glob(x.compile_matcher()))
+                .map_err(|e| format!("invalid glob {}: {}", pattern, e)),
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Glob(glob) => glob.is_match(name),
+            NamePattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+// 配置文件中的文件夹遍历规则, 未设置的字段使用默认值.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DescendConfig {
+    pub include: Vec<String>, // 为空时进入所有文件夹.
+    pub exclude: Vec<String>, // 优先于 include.
+    pub extras: Vec<String>,  // 特典等文件夹, 只有 descend_extras 时才进入.
+    pub descend_extras: bool,
+    pub max_depth: usize, // 动漫文件夹下的第一层深度为 1.
+}
+
+impl Default for DescendConfig {
+    fn default() -> Self {
+        DescendConfig {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            extras: DEFAULT_EXTRAS.map(String::from).to_vec(),
+            descend_extras: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+// 建立动漫索引时进入哪些子文件夹.
+#[derive(Debug, Clone)]
+pub struct DescendRules {
+    include: Vec<NamePattern>,
+    exclude: Vec<NamePattern>,
+    extras: Vec<NamePattern>,
+    descend_extras: bool,
+    max_depth: usize,
+}
+
+impl Default for DescendRules {
+    fn default() -> Self {
+        DescendRules::new(&DescendConfig::default()).unwrap()
+    }
+}
+
+impl DescendRules {
+    pub fn new(config: &DescendConfig) -> Result<DescendRules, String> {
+        let patterns = |x: &[String]| -> Result<Vec<_>, String> {
+            x.iter().map(|x| NamePattern::new(x)).collect()
+        };
+        Ok(DescendRules {
+            include: patterns(&config.include)?,
+            exclude: patterns(&config.exclude)?,
+            extras: patterns(&config.extras)?,
+            descend_extras: config.descend_extras,
+            max_depth: config.max_depth,
+        })
+    }
+
+    // depth 是文件夹本身的深度.
+    pub fn should_descend(&self, name: &str, depth: usize) -> bool {
+        let any = |patterns: &[NamePattern]| patterns.iter().any(|x| x.is_match(name));
+        if depth > self.max_depth || any(&self.exclude) {
+            return false;
+        }
+        if any(&self.extras) {
+            return self.descend_extras;
+        }
+        self.include.is_empty() || any(&self.include)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_descend() {
+        let rules = DescendRules::default();
+        // 短名称和中文名称的季度文件夹也要进入.
+        for name in [
+            "S2",
+            "第二季",
+            "Season 01",
+            "[VCB-Studio] AIR [Ma10p_1080p]",
+        ] {
+            assert!(rules.should_descend(name, 1), "{}", name);
+        }
+        assert!(!rules.should_descend("sps", 1));
+        assert!(!rules.should_descend("S2", 4));
+
+        let rules = DescendRules::new(&DescendConfig {
+            include: vec!["re:^(S\\d+|Season \\d+)$".to_string()],
+            exclude: vec!["*Menu*".to_string()],
+            descend_extras: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(rules.should_descend("S2", 1));
+        assert!(rules.should_descend("Scans", 1));
+        assert!(!rules.should_descend("第二季", 1));
+        assert!(!rules.should_descend("Menus", 1));
+
+        assert!(NamePattern::new("re:(").is_err());
+    }
+}
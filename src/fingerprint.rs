@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::store;
+
+// 头尾各读取的字节数.
+const SAMPLE: u64 = 4 * 1024 * 1024;
+// 小于它的文件不计算指纹, 空文件和占位文件的内容相同不能说明是同一个视频.
+const MIN_SIZE: u64 = 1024 * 1024;
+
+// 文件内容的指纹: 大小和头尾部分的哈希.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    pub fn compute(path: &Path) -> io::Result<Fingerprint> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut hasher = Xxh3::new();
+        let mut buf = vec![0; SAMPLE as usize];
+        let mut update = |file: &mut File, len: u64| -> io::Result<()> {
+            let buf = &mut buf[..len as usize];
+            file.read_exact(buf)?;
+            hasher.update(buf);
+            Ok(())
+        };
+        if size <= SAMPLE * 2 {
+            update(&mut file, size)?;
+        } else {
+            update(&mut file, SAMPLE)?;
+            file.seek(SeekFrom::Start(size - SAMPLE))?;
+            update(&mut file, SAMPLE)?;
+        }
+        Ok(Fingerprint {
+            size,
+            hash: hasher.digest(),
+        })
+    }
+}
+
+// 缓存的一个文件, 大小和修改时间不变时复用指纹.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    size: u64,
+    modified: u64, // 纳秒.
+    fingerprint: Fingerprint,
+}
+
+// 指纹缓存, 保存在 map 文件旁边的 "<map 文件>.fingerprints.json".
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FingerprintCache {
+    entries: HashMap<String, Entry>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl FingerprintCache {
+    pub fn path(map_path: &Path) -> PathBuf {
+        let mut name = map_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".fingerprints.json");
+        map_path.with_file_name(name)
+    }
+
+    // 缓存不存在或者损坏时重新计算.
+    pub fn load(path: &Path) -> FingerprintCache {
+        fs::read(path)
+            .ok()
+            .and_then(|x| serde_json::from_slice(&x).ok())
+            .unwrap_or_default()
+    }
+
+    // 有变化时才写入, 同时去掉已经不存在的文件.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.entries.retain(|x, _| Path::new(x).is_file());
+        store::write_atomic(path, serde_json::to_string(self)?.as_bytes())?;
+        self.changed = false;
+        Ok(())
+    }
+
+    // 太小或者读取失败的文件返回 None.
+    pub fn get(&mut self, path: &Path) -> Option<Fingerprint> {
+        let metadata = fs::metadata(path).ok()?;
+        if metadata.len() < MIN_SIZE {
+            return None;
+        }
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos() as u64)
+            .unwrap_or_default();
+        let key = path.to_string_lossy().to_string();
+        if let Some(entry) = self.entries.get(&key) {
+            if entry.size == metadata.len() && entry.modified == modified {
+                return Some(entry.fingerprint);
+            }
+        }
+        let fingerprint = Fingerprint::compute(path).ok()?;
+        self.entries.insert(
+            key,
+            Entry {
+                size: metadata.len(),
+                modified,
+                fingerprint,
+            },
+        );
+        self.changed = true;
+        Some(fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn fingerprint() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let dir = tep_dir.path();
+        let mut content = vec![0u8; (SAMPLE * 3) as usize];
+        fs::write(dir.join("a.mkv"), &content).unwrap();
+        fs::write(dir.join("renamed.mkv"), &content).unwrap();
+        // 中间的内容不参与计算, 结尾不同时指纹不同.
+        content[SAMPLE as usize * 3 / 2] = 1;
+        fs::write(dir.join("middle.mkv"), &content).unwrap();
+        *content.last_mut().unwrap() = 1;
+        fs::write(dir.join("end.mkv"), &content).unwrap();
+        fs::write(dir.join("small.mkv"), b"a").unwrap();
+
+        let path = FingerprintCache::path(&dir.join("data.yaml"));
+        assert!(path.ends_with("data.yaml.fingerprints.json"));
+        let mut cache = FingerprintCache::load(&path);
+        let a = cache.get(&dir.join("a.mkv")).unwrap();
+        assert_eq!(a.size, SAMPLE * 3);
+        assert_eq!(cache.get(&dir.join("renamed.mkv")), Some(a));
+        assert_eq!(cache.get(&dir.join("middle.mkv")), Some(a));
+        assert_ne!(cache.get(&dir.join("end.mkv")), Some(a));
+        assert_eq!(cache.get(&dir.join("small.mkv")), None);
+
+        fs::remove_file(dir.join("middle.mkv")).unwrap();
+        cache.save(&path).unwrap();
+        let mut cache = FingerprintCache::load(&path);
+        assert_eq!(cache.entries.len(), 3);
+        assert_eq!(cache.get(&dir.join("a.mkv")), Some(a));
+        assert!(!cache.changed);
+    }
+}
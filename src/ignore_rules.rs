@@ -0,0 +1,96 @@
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// 源文件夹下的忽略文件, 使用 gitignore 语法.
+pub const IGNORE_FILE: &str = ".reflinkignore";
+
+// 一个源文件夹的忽略规则, 配置中的规则在前, 忽略文件中的规则可以覆盖它们.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    gitignore: Gitignore,
+}
+
+impl IgnoreRules {
+    pub fn new(root: &Path, patterns: &[String]) -> Result<IgnoreRules, String> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in patterns {
+            builder
+                .add_line(Some(PathBuf::from("config")), pattern)
+                .map_err(|e| format!("invalid ignore pattern {}: {}", pattern, e))?;
+        }
+        let path = root.join(IGNORE_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("read {} failed: {}", path.display(), e)),
+        };
+        // from 只用于记录规则的来源, 所以直接写上行号.
+        for (i, line) in content.lines().enumerate() {
+            let from = format!("{}:{}", IGNORE_FILE, i + 1);
+            builder
+                .add_line(Some(PathBuf::from(&from)), line)
+                .map_err(|e| format!("invalid ignore pattern at {}: {}", from, e))?;
+        }
+        let gitignore = builder.build().map_err(|e| e.to_string())?;
+        Ok(IgnoreRules { gitignore })
+    }
+
+    // 被忽略时返回匹配的规则, 比如 ".reflinkignore:3: *.torrent".
+    // path 是相对于源文件夹的路径, 所在的文件夹被忽略时也算.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<String> {
+        match self.gitignore.matched_path_or_any_parents(path, is_dir) {
+            Match::Ignore(glob) => Some(format!(
+                "{}: {}",
+                glob.from().unwrap_or(Path::new("")).display(),
+                glob.original()
+            )),
+            Match::None | Match::Whitelist(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn matched() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let root = tep_dir.path();
+        let patterns = ["*.parts".to_string(), "*.torrent".to_string()];
+        let rules = IgnoreRules::new(root, &patterns).unwrap();
+        assert_eq!(
+            rules.matched(Path::new("a.parts"), false).as_deref(),
+            Some("config: *.parts")
+        );
+        assert_eq!(rules.matched(Path::new("a.mkv"), false), None);
+
+        fs::write(
+            root.join(IGNORE_FILE),
+            "# 音乐\nmusic/\n!keep.torrent\n*.!qB\n",
+        )
+        .unwrap();
+        let rules = IgnoreRules::new(root, &patterns).unwrap();
+        assert_eq!(
+            rules.matched(Path::new("music"), true).as_deref(),
+            Some(".reflinkignore:2: music/")
+        );
+        assert_eq!(rules.matched(Path::new("music"), false), None);
+        assert_eq!(rules.matched(Path::new("keep.torrent"), false), None);
+        assert_eq!(
+            rules.matched(Path::new("AIR/01.mkv.!qB"), false).as_deref(),
+            Some(".reflinkignore:4: *.!qB")
+        );
+        assert_eq!(
+            rules.matched(Path::new("music/01.flac"), false).as_deref(),
+            Some(".reflinkignore:2: music/")
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, DirEntry},
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{cache::Cache, scan, store};
+
+// 过滤文件夹中的子项, 返回名称和需要进入的文件夹路径, 文件的路径为空.
+// 第二个参数是子项的深度.
+pub type Filter<'a> = &'a (dyn Fn(DirEntry, usize) -> Option<(String, PathBuf)> + Sync);
+
+// 一个文件夹的索引.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct DirIndex {
+    modified: u64, // 纳秒, 读取失败时为 0.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    dirs: BTreeMap<String, DirIndex>,
+}
+
+impl DirIndex {
+    // 修改时间变化的文件夹重新读取, 没有变化的只检查子文件夹.
+    // 返回是否有变化.
+    fn refresh(&mut self, path: &Path, filter: Filter, depth: usize) -> bool {
+        let modified = modified(path);
+        if modified != 0 && modified == self.modified {
+            let mut changed = false;
+            for (name, dir) in &mut self.dirs {
+                changed |= dir.refresh(&path.join(name), filter, depth + 1);
+            }
+            return changed;
+        }
+
+        let mut old = std::mem::take(&mut self.dirs);
+        self.modified = modified;
+        self.files.clear();
+        if let Ok(entries) = fs::read_dir(path) {
+            // HACK: 关于 flatten 和 filter_map 的讨论:
+            // https://users.rust-lang.org/t/where-is-flatten-skipping-none-documented/89255/21
+            // https://github.com/rust-lang/rust-clippy/issues/9377
+            // https://github.com/rust-lang/rust/pull/99230
+            // https://rust.godbolt.org/z/aG444qGdW
+            // filter_map(|x| x), filter_map(identity), flatten.
+            for (name, dir_path) in entries.flatten().filter_map(|x| filter(x, depth)) {
+                if dir_path.as_os_str() == "" {
+                    self.files.push(name);
+                    continue;
+                }
+                let mut dir = old.remove(&name).unwrap_or_default();
+                dir.refresh(&dir_path, filter, depth + 1);
+                self.dirs.insert(name, dir);
+            }
+        }
+        self.files.sort();
+        true
+    }
+
+    fn to_cache(&self) -> Cache {
+        let mut cache = Cache::default();
+        for file in &self.files {
+            cache.insert_none(file);
+        }
+        for (name, dir) in &self.dirs {
+            cache.insert(name, dir.to_cache());
+        }
+        cache
+    }
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|x| x.modified())
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+// 动漫文件夹的索引, 保存在 map 文件旁边的 "<map 文件>.index.json".
+// 记录每个文件夹的修改时间, 只重新读取有变化的文件夹.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AnimeIndex {
+    rules: String, // 建立索引时的过滤规则, 规则变化后索引失效.
+    dirs: HashMap<String, DirIndex>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl AnimeIndex {
+    pub fn new(rules: &str) -> AnimeIndex {
+        AnimeIndex {
+            rules: rules.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn path(map_path: &Path) -> PathBuf {
+        let mut name = map_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".index.json");
+        map_path.with_file_name(name)
+    }
+
+    // 索引不存在, 损坏或者规则不同时重新建立.
+    pub fn load(path: &Path, rules: &str) -> AnimeIndex {
+        fs::read(path)
+            .ok()
+            .and_then(|x| serde_json::from_slice::<AnimeIndex>(&x).ok())
+            .filter(|x| x.rules == rules)
+            .unwrap_or_else(|| AnimeIndex::new(rules))
+    }
+
+    // 有变化时才写入, 同时去掉已经不存在的动漫文件夹.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.dirs.retain(|x, _| Path::new(x).is_dir());
+        store::write_atomic(path, serde_json::to_string(self)?.as_bytes())?;
+        self.changed = false;
+        Ok(())
+    }
+
+    // 更新一个动漫文件夹的索引并转换成 Cache.
+    pub fn fetch(&mut self, path: &Path, filter: Filter) -> Cache {
+        self.fetch_all(&[path.to_path_buf()], filter, 1)
+            .pop()
+            .unwrap()
+    }
+
+    // 用 jobs 个线程同时更新多个动漫文件夹, 结果和 paths 的顺序相同.
+    pub fn fetch_all(&mut self, paths: &[PathBuf], filter: Filter, jobs: usize) -> Vec<Cache> {
+        let dirs: Vec<_> = paths
+            .iter()
+            .map(|x| {
+                let key = x.to_string_lossy().to_string();
+                let dir = self.dirs.remove(&key).unwrap_or_default();
+                (x, key, dir)
+            })
+            .collect();
+        let dirs = scan::map(dirs, jobs, |(path, key, mut dir)| {
+            let changed = dir.refresh(path, filter, 1);
+            (key, dir, changed)
+        });
+        dirs.into_iter()
+            .map(|(key, dir, changed)| {
+                self.changed |= changed;
+                let cache = dir.to_cache();
+                self.dirs.insert(key, dir);
+                cache
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn fetch() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let anime = tep_dir.path().join("AIR");
+        fs::create_dir_all(anime.join("Season 01")).unwrap();
+        fs::create_dir_all(anime.join("Scans")).unwrap();
+        fs::write(anime.join("Season 01").join("01.mkv"), b"").unwrap();
+        fs::write(anime.join("cover.jpg"), b"").unwrap();
+        let filter = |x: DirEntry, _: usize| {
+            let name = x.file_name().into_string().ok()?;
+            match x.file_type().ok()?.is_dir() {
+                true => (name != "Scans").then(|| (name, x.path())),
+                false => name.ends_with(".mkv").then(|| (name, PathBuf::new())),
+            }
+        };
+
+        let path = AnimeIndex::path(&tep_dir.path().join("data.yaml"));
+        let mut index = AnimeIndex::load(&path, "mkv");
+        let cache = index.fetch(&anime, &filter);
+        assert_eq!(
+            cache,
+            Cache::from([("Season 01", Cache::from([("01.mkv", Cache::None)]))])
+        );
+        index.save(&path).unwrap();
+
+        // 没有变化的文件夹不再读取, 改动索引后结果不变说明没有重新读取.
+        let mut index = AnimeIndex::load(&path, "mkv");
+        let dir = index.dirs.values_mut().next().unwrap();
+        dir.dirs
+            .get_mut("Season 01")
+            .unwrap()
+            .files
+            .push("fake.mkv".to_string());
+        let cache = index.fetch(&anime, &filter);
+        assert!(cache.get("Season 01/fake.mkv").is_some());
+        assert!(!index.changed);
+
+        // 子文件夹变化时只重新读取它.
+        fs::write(anime.join("Season 01").join("02.mkv"), b"").unwrap();
+        let cache = index.fetch(&anime, &filter);
+        assert!(cache.get("Season 01/02.mkv").is_some());
+        assert!(cache.get("Season 01/fake.mkv").is_none());
+        assert!(index.changed);
+
+        // 多个文件夹同时更新.
+        let kanon = tep_dir.path().join("Kanon");
+        fs::create_dir_all(&kanon).unwrap();
+        fs::write(kanon.join("01.mkv"), b"").unwrap();
+        let caches = index.fetch_all(&[kanon, anime], &filter, 4);
+        assert_eq!(caches[0], Cache::from([("01.mkv", Cache::None)]));
+        assert!(caches[1].get("Season 01/02.mkv").is_some());
+
+        // 规则变化后重新建立.
+        assert_eq!(AnimeIndex::load(&path, "mkv").dirs.len(), 1);
+        assert!(AnimeIndex::load(&path, "mp4").dirs.is_empty());
+    }
+}
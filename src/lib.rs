@@ -1,4 +1,20 @@
 pub mod cache;
 pub mod config;
 pub mod data;
+pub mod descend;
+pub mod fingerprint;
+pub mod ignore_rules;
+pub mod index;
+pub mod linker;
+pub mod lock;
+pub mod matcher;
+pub mod media;
+pub mod migrate;
+pub mod plan;
+pub mod reflink;
+pub mod release;
+pub mod rename;
+pub mod scan;
 pub mod source_anime_map;
+pub mod store;
+pub mod title;
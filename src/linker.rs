@@ -0,0 +1,518 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::reflink;
+
+// 链接时是否包含某个文件, 参数是源文件的路径.
+pub type Filter<'a> = &'a dyn Fn(&Path) -> bool;
+
+// 链接方式.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMode {
+    Reflink,         // FICLONE 克隆, 需要对齐时用 FICLONERANGE.
+    Hardlink,        // 硬链接.
+    Symlink,         // 绝对路径的软链接.
+    RelativeSymlink, // 相对路径的软链接.
+    Copy,            // 普通复制.
+}
+
+impl LinkMode {
+    pub fn linker(&self) -> Box<dyn Linker> {
+        match self {
+            LinkMode::Reflink => Box::new(Reflink),
+            LinkMode::Hardlink => Box::new(Hardlink),
+            LinkMode::Symlink => Box::new(Symlink { relative: false }),
+            LinkMode::RelativeSymlink => Box::new(Symlink { relative: true }),
+            LinkMode::Copy => Box::new(Copy),
+        }
+    }
+}
+
+impl fmt::Display for LinkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use LinkMode::*;
+        match self {
+            Reflink => write!(f, "reflink"),
+            Hardlink => write!(f, "hardlink"),
+            Symlink => write!(f, "symlink"),
+            RelativeSymlink => write!(f, "relative_symlink"),
+            Copy => write!(f, "copy"),
+        }
+    }
+}
+
+impl FromStr for LinkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reflink" => Ok(LinkMode::Reflink),
+            "hardlink" => Ok(LinkMode::Hardlink),
+            "symlink" => Ok(LinkMode::Symlink),
+            "relative_symlink" => Ok(LinkMode::RelativeSymlink),
+            "copy" => Ok(LinkMode::Copy),
+            _ => Err(format!("unknown link mode: {}", s)),
+        }
+    }
+}
+
+// 链接单个文件的方式, 文件夹总是重新创建.
+pub trait Linker {
+    fn mode(&self) -> LinkMode;
+
+    // target 调用前保证不存在.
+    fn link_file(&self, source: &Path, target: &Path) -> io::Result<()>;
+
+    // 链接后是否需要复制元数据, 硬链接和软链接不需要.
+    fn copy_metadata(&self) -> bool {
+        true
+    }
+}
+
+pub struct Reflink;
+
+impl Linker for Reflink {
+    fn mode(&self) -> LinkMode {
+        LinkMode::Reflink
+    }
+
+    fn link_file(&self, source: &Path, target: &Path) -> io::Result<()> {
+        reflink::clone_file(source, target)
+    }
+}
+
+pub struct Hardlink;
+
+impl Linker for Hardlink {
+    fn mode(&self) -> LinkMode {
+        LinkMode::Hardlink
+    }
+
+    fn link_file(&self, source: &Path, target: &Path) -> io::Result<()> {
+        fs::hard_link(source, target)
+    }
+
+    fn copy_metadata(&self) -> bool {
+        false
+    }
+}
+
+pub struct Symlink {
+    relative: bool,
+}
+
+impl Linker for Symlink {
+    fn mode(&self) -> LinkMode {
+        if self.relative {
+            LinkMode::RelativeSymlink
+        } else {
+            LinkMode::Symlink
+        }
+    }
+
+    fn link_file(&self, source: &Path, target: &Path) -> io::Result<()> {
+        let source = fs::canonicalize(source)?;
+        let link = if self.relative {
+            let parent = target.parent().unwrap_or(Path::new("."));
+            relative_path(&fs::canonicalize(parent)?, &source)
+        } else {
+            source
+        };
+        symlink(&link, target)
+    }
+
+    fn copy_metadata(&self) -> bool {
+        false
+    }
+}
+
+pub struct Copy;
+
+impl Linker for Copy {
+    fn mode(&self) -> LinkMode {
+        LinkMode::Copy
+    }
+
+    fn link_file(&self, source: &Path, target: &Path) -> io::Result<()> {
+        fs::copy(source, target).map(|_| ())
+    }
+}
+
+// 单个文件的错误.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+// 链接一棵目录树的结果.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub files: usize,             // 成功链接的文件数.
+    pub dirs: usize,              // 成功创建的文件夹数.
+    pub skipped: usize,           // 被 filter 排除的文件数.
+    pub errors: Vec<FileError>,   // 失败的文件, 不会中断整个遍历.
+    pub warnings: Vec<FileError>, // 不影响结果的问题, 比如没有权限复制的扩展属性.
+    pub targets: Vec<PathBuf>,    // 成功链接的文件的目标.
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn push_error(&mut self, path: &Path, error: io::Error) {
+        self.errors.push(FileError {
+            path: path.to_path_buf(),
+            error,
+        });
+    }
+
+    // 复制元数据, 没有复制的扩展属性只作为警告.
+    fn copy_metadata(&mut self, source: &Path, target: &Path, metadata: &fs::Metadata) {
+        match reflink::copy_metadata(source, target, metadata) {
+            Ok(skipped) => self
+                .warnings
+                .extend(skipped.into_iter().map(|name| FileError {
+                    path: source.to_path_buf(),
+                    error: io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("extended attribute {} not copied", name),
+                    ),
+                })),
+            Err(e) => self.push_error(source, e),
+        }
+    }
+}
+
+// 把 source 链接为 target, 可以改名. 只链接 filter 包含的文件.
+pub fn link_to(linker: &dyn Linker, source: &Path, target: &Path, filter: Filter) -> Report {
+    let mut report = Report::default();
+    link_entry(linker, source, target, filter, &mut report);
+    report
+}
+
+// 把 source 下的所有文件直接链接到 target_dir 下, 不保留子文件夹.
+// 同名的文件只链接第一个, 已经存在的目标不覆盖, 除非 replaceable 包含它.
+// 两种情况都作为这个文件的错误报告.
+pub fn link_flat(
+    linker: &dyn Linker,
+    source: &Path,
+    target_dir: &Path,
+    filter: Filter,
+    replaceable: Filter,
+) -> Report {
+    let mut report = Report::default();
+    if let Err(e) = fs::create_dir_all(target_dir) {
+        report.push_error(target_dir, e);
+        return report;
+    }
+    let mut files = Vec::new();
+    flat_files(source, &mut files, &mut report);
+    let mut used = HashSet::new();
+    for file in files {
+        let Some(name) = file.file_name() else {
+            report.push_error(&file, io::ErrorKind::InvalidInput.into());
+            continue;
+        };
+        let target = target_dir.join(name);
+        if !filter(&file) {
+            report.skipped += 1;
+            continue;
+        }
+        let exists = |message: &str| {
+            let message = format!("{} {}", target.display(), message);
+            io::Error::new(io::ErrorKind::AlreadyExists, message)
+        };
+        if !used.insert(target.clone()) {
+            report.push_error(&file, exists("is linked from another file"));
+        } else if fs::symlink_metadata(&target).is_ok() && !replaceable(&target) {
+            report.push_error(&file, exists("already exists"));
+        } else {
+            link_entry(linker, &file, &target, filter, &mut report);
+        }
+    }
+    report
+}
+
+// 平铺时链接的文件, 同一层按路径排序, 读取失败的项记录到 report.
+fn flat_files(source: &Path, files: &mut Vec<PathBuf>, report: &mut Report) {
+    if !source.is_dir() {
+        return files.push(source.to_path_buf());
+    }
+    let entries = match fs::read_dir(source) {
+        Ok(entries) => entries,
+        Err(e) => return report.push_error(source, e),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(e) => report.push_error(source, e),
+        }
+    }
+    paths.sort();
+    for path in paths {
+        flat_files(&path, files, report);
+    }
+}
+
+// 按回退链依次尝试 link_to.
+pub fn link_to_with_fallback(
+    modes: &[LinkMode],
+    source: &Path,
+    target: &Path,
+    filter: Filter,
+) -> Result<(LinkMode, Report), Vec<(LinkMode, Report)>> {
+    fallback(modes, |linker| link_to(linker, source, target, filter))
+}
+
+// 按回退链依次尝试 link, 返回第一个完全成功的方式和它的报告.
+// 全部失败时返回每种方式的报告.
+pub fn fallback(
+    modes: &[LinkMode],
+    link: impl Fn(&dyn Linker) -> Report,
+) -> Result<(LinkMode, Report), Vec<(LinkMode, Report)>> {
+    let mut failed = Vec::new();
+    for mode in modes {
+        let report = link(mode.linker().as_ref());
+        if report.is_ok() {
+            return Ok((*mode, report));
+        }
+        failed.push((*mode, report));
+    }
+    Err(failed)
+}
+
+// 递归链接, 文件夹的元数据要在子项完成后再设置, 否则修改时间会被覆盖.
+fn link_entry(
+    linker: &dyn Linker,
+    source: &Path,
+    target: &Path,
+    filter: Filter,
+    report: &mut Report,
+) {
+    let metadata = match fs::symlink_metadata(source) {
+        Ok(metadata) => metadata,
+        Err(e) => return report.push_error(source, e),
+    };
+    let file_type = metadata.file_type();
+
+    if file_type.is_dir() {
+        match fs::create_dir(target) {
+            Ok(_) => report.dirs += 1,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return report.push_error(source, e),
+        }
+        match fs::read_dir(source) {
+            Ok(entries) => entries.for_each(|entry| match entry {
+                Ok(entry) => link_entry(
+                    linker,
+                    &entry.path(),
+                    &target.join(entry.file_name()),
+                    filter,
+                    report,
+                ),
+                Err(e) => report.push_error(source, e),
+            }),
+            Err(e) => report.push_error(source, e),
+        }
+        report.copy_metadata(source, target, &metadata);
+        return;
+    }
+
+    if !filter(source) {
+        report.skipped += 1;
+        return;
+    }
+    // 先删除已有文件, 避免写穿指向源文件的硬链接.
+    if let Err(e) = remove_existing(target) {
+        return report.push_error(source, e);
+    }
+    if file_type.is_symlink() {
+        match fs::read_link(source).and_then(|link| symlink(&link, target)) {
+            Ok(_) => report.targets.push(target.to_path_buf()),
+            Err(e) => report.push_error(source, e),
+        }
+        return;
+    }
+    match linker.link_file(source, target) {
+        Ok(_) => {
+            report.files += 1;
+            report.targets.push(target.to_path_buf());
+        }
+        Err(e) => return report.push_error(source, e),
+    }
+    if linker.copy_metadata() {
+        report.copy_metadata(source, target, &metadata);
+    }
+}
+
+fn remove_existing(target: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "target is a directory",
+        )),
+        Ok(_) => fs::remove_file(target),
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn symlink(link: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// 计算从 from 文件夹到 to 的相对路径, 两者都必须是绝对路径.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    (common..from.len()).for_each(|_| path.push(".."));
+    to[common..].iter().for_each(|c| path.push(c));
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::{MediaKind, MediaRules};
+    use tempfile::*;
+
+    fn temp_source(tep_dir: &TempDir) -> (PathBuf, PathBuf) {
+        let source = tep_dir.path().join("source");
+        let target = tep_dir.path().join("target");
+        fs::create_dir_all(source.join("Season 01")).unwrap();
+        fs::create_dir(&target).unwrap();
+        fs::write(source.join("a.mkv"), b"a").unwrap();
+        fs::write(source.join("Season 01").join("b.mkv"), b"b").unwrap();
+        fs::write(source.join("readme.txt"), b"c").unwrap();
+        (source, target)
+    }
+
+    #[test]
+    fn link_to() {
+        let media = MediaRules {
+            link: vec![MediaKind::Video],
+            ..Default::default()
+        };
+        let filter = |path: &Path| media.should_link(path);
+        for mode in [
+            LinkMode::Hardlink,
+            LinkMode::Symlink,
+            LinkMode::RelativeSymlink,
+            LinkMode::Copy,
+        ] {
+            let tep_dir = tempdir_in("./").unwrap();
+            let (source, target) = temp_source(&tep_dir);
+            let target = target.join("source");
+            let report = super::link_to(mode.linker().as_ref(), &source, &target, &filter);
+            assert!(report.is_ok(), "{} {:?}", mode, report);
+            assert_eq!((report.dirs, report.files, report.skipped), (2, 2, 1));
+            assert!(!target.join("readme.txt").exists());
+            assert_eq!(fs::read(target.join("Season 01/b.mkv")).unwrap(), b"b");
+            // 重复链接会覆盖已有文件.
+            assert!(super::link_to(mode.linker().as_ref(), &source, &target, &filter).is_ok());
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let (source, target) = temp_source(&tep_dir);
+
+        // 不支持 reflink 的文件系统上会继续尝试 copy.
+        let filter = |_: &Path| true;
+        let modes = [LinkMode::Reflink, LinkMode::Copy];
+        let link = |linker: &dyn Linker| super::link_to(linker, &source, &target, &filter);
+        let (mode, report) = super::fallback(&modes, link).unwrap();
+        assert!(report.warnings.is_empty());
+        assert!([LinkMode::Reflink, LinkMode::Copy].contains(&mode));
+        assert_eq!(fs::read(target.join("a.mkv")).unwrap(), b"a");
+
+        let failed = super::fallback(&[], link).unwrap_err();
+        assert!(failed.is_empty());
+
+        // 链接失败的方式和它的报告都要返回.
+        let missing = tep_dir.path().join("missing");
+        let failed = super::fallback(&[LinkMode::Copy], |linker| {
+            super::link_to(linker, &missing, &target, &filter)
+        })
+        .unwrap_err();
+        assert_eq!(failed[0].0, LinkMode::Copy);
+        assert_eq!(failed[0].1.errors.len(), 1);
+
+        // 单个文件可以改名.
+        let renamed = target.join("AIR - 01.mkv");
+        super::link_to_with_fallback(&[LinkMode::Copy], &source.join("a.mkv"), &renamed, &filter)
+            .unwrap();
+        assert_eq!(fs::read(renamed).unwrap(), b"a");
+    }
+
+    #[test]
+    fn link_flat() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let (source, target) = temp_source(&tep_dir);
+        let linker = LinkMode::Copy.linker();
+        let all = |_: &Path| true;
+        let none = |_: &Path| false;
+
+        let flat = target.join("flat");
+        let report = super::link_flat(linker.as_ref(), &source, &flat, &all, &none);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.targets.len(), 3);
+        let mut names: Vec<_> = fs::read_dir(&flat)
+            .unwrap()
+            .flatten()
+            .map(|x| x.file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.mkv", "b.mkv", "readme.txt"]);
+
+        // 已经存在的目标不覆盖, 除非是可以替换的.
+        fs::write(flat.join("a.mkv"), b"library").unwrap();
+        let report = super::link_flat(linker.as_ref(), &source, &flat, &all, &none);
+        assert_eq!(report.errors.len(), 3);
+        assert_eq!(fs::read(flat.join("a.mkv")).unwrap(), b"library");
+        let report = super::link_flat(linker.as_ref(), &source, &flat, &all, &all);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(fs::read(flat.join("a.mkv")).unwrap(), b"a");
+
+        // 不同文件夹中的同名文件只链接第一个.
+        fs::create_dir_all(source.join("Disc2")).unwrap();
+        fs::write(source.join("Disc2").join("a.mkv"), b"disc2").unwrap();
+        let flat = target.join("flat2");
+        let report = super::link_flat(linker.as_ref(), &source, &flat, &all, &none);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].path, source.join("a.mkv"));
+        assert_eq!(fs::read(flat.join("a.mkv")).unwrap(), b"disc2");
+    }
+
+    #[test]
+    fn relative_path() {
+        assert_eq!(
+            super::relative_path(Path::new("/a/b/c"), Path::new("/a/d/e.mkv")),
+            PathBuf::from("../../d/e.mkv")
+        );
+    }
+}
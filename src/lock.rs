@@ -0,0 +1,177 @@
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+// 等待锁时的轮询间隔.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// map 文件的进程锁, 从读取到写入都要持有.
+// 锁在单独的 "<map 文件>.lock" 上, 因为 map 文件写入时会被替换.
+// 使用 flock, 进程被杀死时锁会自动释放; 锁文件中残留的 pid 说明上次没有正常退出.
+pub struct MapLock {
+    file: File,
+    path: PathBuf,
+    exclusive: bool,
+}
+
+impl MapLock {
+    // exclusive 为 false 时是共享锁, 只读的运行之间不互斥.
+    // wait 为 None 时一直等待, 为 0 时立即失败.
+    // 共享锁不创建任何文件, 锁文件不存在时返回 None.
+    pub fn acquire(
+        map_path: &Path,
+        exclusive: bool,
+        wait: Option<Duration>,
+    ) -> Result<Option<MapLock>, Box<dyn Error>> {
+        let mut name = map_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        let path = map_path.with_file_name(name);
+        if exclusive {
+            if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        // 写入的运行在读取前就会创建锁文件, 锁文件不存在时不会有写到一半的 map 文件.
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(exclusive)
+            .create(exclusive)
+            .truncate(false)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(e) if !exclusive && e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("open lock {} failed: {}", path.display(), e).into()),
+        };
+
+        let start = Instant::now();
+        while !try_lock(&file, exclusive)? {
+            if wait.is_some_and(|wait| start.elapsed() >= wait) {
+                let holder = match read_pid(&mut file) {
+                    Some(pid) => format!("process {}", pid),
+                    None => "another process".to_string(),
+                };
+                let msg = format!("{} is locked by {}", map_path.display(), holder);
+                return Err(msg.into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        if exclusive {
+            if let Some(pid) = read_pid(&mut file) {
+                eprintln!(
+                    "removed stale lock of process {}, check {} for an unfinished run",
+                    pid,
+                    map_path.display()
+                );
+            }
+            file.set_len(0)?;
+            file.rewind()?;
+            write!(file, "{}", process::id())?;
+            file.sync_all()?;
+        }
+        Ok(Some(MapLock {
+            file,
+            path,
+            exclusive,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// 正常退出时清空 pid, flock 随文件关闭释放.
+impl Drop for MapLock {
+    fn drop(&mut self) {
+        if self.exclusive {
+            let _ = self.file.set_len(0);
+        }
+    }
+}
+
+// 锁文件中记录的 pid, 没有记录时为 None.
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+// 成功返回 true, 被占用时返回 false.
+#[cfg(unix)]
+fn try_lock(file: &File, exclusive: bool) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let operation = match exclusive {
+        true => libc::LOCK_EX,
+        false => libc::LOCK_SH,
+    };
+    // SAFETY: fd 在调用期间有效.
+    let ret = unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_: &File, _: bool) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn acquire() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let path = tep_dir.path().join("data").join("data.yaml");
+        let wait = Some(Duration::ZERO);
+
+        // 只读运行不创建锁文件.
+        assert!(MapLock::acquire(&path, false, wait).unwrap().is_none());
+        assert!(!tep_dir.path().join("data").exists());
+
+        let lock = MapLock::acquire(&path, true, wait).unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(lock.path()).unwrap(),
+            process::id().to_string()
+        );
+        let err = MapLock::acquire(&path, true, wait).err().unwrap();
+        assert!(
+            err.to_string().contains(&process::id().to_string()),
+            "{}",
+            err
+        );
+        assert!(MapLock::acquire(&path, false, wait).is_err());
+        let lock_path = lock.path().to_path_buf();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&lock_path).unwrap(), "");
+
+        // 锁文件存在后只读运行也加锁, 但不写入 pid.
+        let lock = MapLock::acquire(&path, false, wait).unwrap().unwrap();
+        assert_eq!(fs::read_to_string(lock.path()).unwrap(), "");
+        assert!(MapLock::acquire(&path, true, wait).is_err());
+        drop(lock);
+
+        // 被杀死的进程留下的 pid 不影响加锁.
+        fs::write(&lock_path, "4194304").unwrap();
+        let shared = MapLock::acquire(&path, false, wait).unwrap();
+        assert!(MapLock::acquire(&path, false, wait).is_ok());
+        drop(shared);
+        assert!(MapLock::acquire(&path, true, wait).is_ok());
+    }
+}
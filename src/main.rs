@@ -1,32 +1,129 @@
+use chrono::{NaiveTime, Utc};
 use std::env;
 use std::error::Error;
-use  chrono::{NaiveTime, Utc};
+use std::path::Path;
 
-
-
-use anime_reflink::config::Config;
+use anime_reflink::config::{Action, Config, OutputFormat, RestoreArgs};
 use anime_reflink::data::Data;
+use anime_reflink::lock::MapLock;
+use anime_reflink::plan::PlanFile;
+use anime_reflink::store;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let start_time: NaiveTime = Utc::now().time();
 
-    let config = Config::new(env::args());
+    // 参数错误时输出用法并以非零状态退出.
+    let config = Config::new(env::args()).unwrap_or_else(|e| e.exit());
+    // json 输出时标准输出只有计划.
+    let human = config.format == OutputFormat::Human;
+
+    if human {
+        println!("Run for {}", config.action);
+        if let Some(profile) = &config.profile {
+            println!("With profile {}", profile);
+        }
+        println!("In file {}", config.mapfile_path);
+        for root in &config.roots {
+            println!("In source {}", root.source);
+            println!("In anime {}", root.anime);
+        }
+    }
+
+    // 从读取到写入都持有锁, 只读的运行使用共享锁.
+    let exclusive = !config.dry_run && !matches!(config.action, Action::Status);
+    let _lock = MapLock::acquire(Path::new(&config.mapfile_path), exclusive, config.lock_wait)?;
 
-    println!("Run for {}", config.action);
-    println!("In file {}", config.mapfile_path);
-    println!("In source {}", config.source_path);
-    println!("In anime {}", config.anime_path);
+    // 恢复不读取 map 文件, 文件损坏时也可以使用.
+    if let Action::Restore(args) = &config.action {
+        return restore(&config, args);
+    }
 
-    let mut data = Data::from_yaml(config);
-    data.push_map_from_dir();
-    data.push_anime_from_dir()?;
-    data.map_animes()?;
+    let mut data = Data::from_yaml(config)?;
+    let before = data.data.clone();
+    let mut tasks = Vec::new();
+    match data.action() {
+        Action::Status => {
+            data.print_status();
+            return Ok(());
+        }
+        Action::Edit(edit) => {
+            let edit = edit.clone();
+            data.edit_map(&edit)?;
+        }
+        Action::Scan => {
+            data.push_map_from_dir()?;
+            data.push_anime_from_dir()?;
+        }
+        Action::Plan(plan) => {
+            let output = plan.output.clone();
+            data.push_map_from_dir()?;
+            data.push_anime_from_dir()?;
+            tasks = data.map_animes()?;
+            if let Some(output) = output {
+                data.plan_file(&tasks)?.write(&output)?;
+            }
+        }
+        Action::Apply(apply) => {
+            let plan = PlanFile::from_path(&apply.plan)?;
+            data.push_map_from_dir()?;
+            data.push_anime_from_dir()?;
+            tasks = data.apply_plan(&plan)?;
+        }
+        Action::Rename(rename) => {
+            let rename = rename.clone();
+            data.rename_maps(&rename)?;
+        }
+        Action::Restore(_) => unreachable!(),
+        Action::Link | Action::Renew => {
+            data.push_map_from_dir()?;
+            data.push_anime_from_dir()?;
+            tasks = data.map_animes()?;
+        }
+    }
 
+    if data.config().dry_run {
+        let plan = data.build_plan(&before, &tasks);
+        match human {
+            true => print!("{}", plan),
+            false => println!("{}", plan.to_json()),
+        }
+        return Ok(());
+    }
+    // 并列或者置信度太低的源没有链接, 列出来交给人工确认.
+    data.print_reviews();
     data.write_yaml()?;
 
-   
     let end_time: NaiveTime = Utc::now().time();
     let diff = end_time - start_time;
     println!("Total time taken to run is {}", diff);
     Ok(())
 }
+
+// 没有指定备份时列出所有备份.
+fn restore(config: &Config, args: &RestoreArgs) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(&config.mapfile_path);
+    let backups = store::list_backups(path)?;
+    let Some(backup) = &args.backup else {
+        for (i, backup) in backups.iter().enumerate() {
+            println!("{:>3}  {}", i + 1, backup.display());
+        }
+        if backups.is_empty() {
+            println!("no backups of {}", path.display());
+        }
+        return Ok(());
+    };
+    let found = match backup.parse::<usize>() {
+        Ok(i) => backups.get(i.wrapping_sub(1)),
+        Err(_) => backups
+            .iter()
+            .find(|x| x.file_name().is_some_and(|x| x == backup.as_str())),
+    };
+    let found = found.ok_or(format!("backup not found: {}", backup))?;
+    if config.dry_run {
+        println!("would restore {}", found.display());
+        return Ok(());
+    }
+    store::restore(path, found, config.backups)?;
+    println!("restored {}", found.display());
+    Ok(())
+}
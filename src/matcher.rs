@@ -0,0 +1,199 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::{cache::NameIndex, plan::MatchReason, title};
+
+// 一个候选动漫和匹配的置信度, 0 到 1.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub anime: String,
+    pub confidence: f64,
+    pub reason: MatchReason,
+}
+
+// 一个源的匹配结果.
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Matched(Candidate),
+    Review(Vec<Candidate>), // 并列或者置信度太低, 需要人工确认.
+    Unmatched,
+}
+
+// 计算每个动漫的置信度, 从高到低排列, 没有任何依据的动漫不列出.
+// 动漫文件夹中有同名的源时置信度为 1.
+// 否则按重合的文件名计算, 每个文件名的权重是包含它的动漫数量的倒数,
+// 比如多个动漫都有的 "NCOP.mkv" 权重很低. 权重之和除以源中的文件数量再开方,
+// 少量重合也有一定分数. 再和标题相似度合并, 两者互相独立, 任一接近 1 时结果接近 1.
+// 标题相似度低于 title_threshold 时不作为依据.
+// names 是所有动漫的文件名索引, owner 是动漫名称.
+// contents 是每个动漫中内容相同的源文件, 和同名的文件一样计算.
+pub fn rank(
+    source: &str,
+    files: &HashSet<String>,
+    animes: &[&str],
+    names: &NameIndex,
+    contents: &HashMap<String, HashSet<String>>,
+    title_threshold: f64,
+) -> Vec<Candidate> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut overlaps: HashMap<&str, Vec<&str>> = HashMap::new();
+    for file in files {
+        let mut owners = names.owners(file);
+        for (anime, same) in contents {
+            if same.contains(file) && !owners.contains(&anime.as_str()) {
+                owners.push(anime);
+            }
+        }
+        counts.insert(file, owners.len());
+        for owner in owners {
+            overlaps.entry(owner).or_default().push(file);
+        }
+    }
+
+    // 有同名的源的动漫直接从索引中找到, 源标题只解析一次.
+    let same_name = names.owners(source);
+    let source_title = title::source_title(source);
+    let mut candidates = Vec::new();
+    for anime in animes {
+        if same_name.contains(anime) {
+            candidates.push(Candidate {
+                anime: anime.to_string(),
+                confidence: 1.0,
+                reason: MatchReason::SourceName,
+            });
+            continue;
+        }
+        let mut overlap = overlaps.remove(anime).unwrap_or_default();
+        overlap.sort();
+        let weight: f64 = overlap.iter().map(|x| 1.0 / counts[x] as f64).sum();
+        let file_score = match files.len() {
+            0 => 0.0,
+            n => (weight / n as f64).sqrt(),
+        };
+        let (title, title_score) = title::score(&source_title, anime)
+            .filter(|x| x.1 >= title_threshold)
+            .unwrap_or_default();
+        let confidence = 1.0 - (1.0 - file_score) * (1.0 - title_score);
+        if confidence <= 0.0 {
+            continue;
+        }
+        let files = overlap.iter().map(|x| x.to_string()).collect();
+        let reason = match overlap {
+            _ if overlap.is_empty() => MatchReason::Title {
+                title,
+                score: title_score,
+            },
+            _ if overlap.iter().any(|x| names.contains(anime, x)) => {
+                MatchReason::FileNames { files }
+            }
+            _ => MatchReason::SameContent { files },
+        };
+        candidates.push(Candidate {
+            anime: anime.to_string(),
+            confidence,
+            reason,
+        });
+    }
+    candidates.sort_by(|a, b| {
+        b.confidence
+            .total_cmp(&a.confidence)
+            .then_with(|| a.anime.cmp(&b.anime))
+    });
+    candidates
+}
+
+// 最高的候选唯一并且不低于 min_confidence 时才接受, 否则不猜测.
+pub fn decide(candidates: Vec<Candidate>, min_confidence: f64) -> Decision {
+    let Some(first) = candidates.first() else {
+        return Decision::Unmatched;
+    };
+    let tie = candidates
+        .get(1)
+        .is_some_and(|x| (first.confidence - x.confidence).abs() < f64::EPSILON);
+    if tie || first.confidence < min_confidence {
+        return Decision::Review(candidates);
+    }
+    Decision::Matched(candidates.into_iter().next().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+
+    #[test]
+    fn rank() {
+        let air = Cache::from([
+            ("AIR [01].mkv", Cache::None),
+            ("AIR [02].mkv", Cache::None),
+            ("NCOP.mkv", Cache::None),
+        ]);
+        let tears = Cache::from([("Season 01", Cache::from([("NCOP.mkv", Cache::None)]))]);
+        let animes = ["AIR [青空]", "true tears [真实之泪]"];
+        let mut names = NameIndex::default();
+        names.insert(animes[0], &air);
+        names.insert(animes[1], &tears);
+        let files = |names: &[&str]| names.iter().map(|x| x.to_string()).collect();
+        let none = HashMap::new();
+
+        // 唯一的文件名比共有的文件名可信.
+        let candidates = super::rank(
+            "[VCB-Studio] 青空",
+            &files(&["AIR [01].mkv", "AIR [03].mkv", "NCOP.mkv"]),
+            &animes,
+            &names,
+            &none,
+            0.8,
+        );
+        let ranked: Vec<_> = candidates.iter().map(|x| x.anime.as_str()).collect();
+        assert_eq!(ranked, ["AIR [青空]", "true tears [真实之泪]"]);
+        assert_eq!(candidates[0].confidence, 1.0);
+        assert!(candidates[1].confidence < 0.5);
+        assert!(matches!(decide(candidates, 0.5), Decision::Matched(x) if x.anime == "AIR [青空]"));
+
+        // 只有共有的文件名时两个动漫并列.
+        let candidates = super::rank("Extras", &files(&["NCOP.mkv"]), &animes, &names, &none, 0.8);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].confidence, candidates[1].confidence);
+        assert!(matches!(decide(candidates, 0.1), Decision::Review(x) if x.len() == 2));
+
+        // 少量重合置信度低.
+        let candidates = super::rank(
+            "Unknown",
+            &files(&["AIR [02].mkv", "b.mkv", "c.mkv", "d.mkv", "e.mkv", "f.mkv"]),
+            &animes,
+            &names,
+            &none,
+            0.8,
+        );
+        assert_eq!(candidates.len(), 1);
+        assert!(matches!(decide(candidates, 0.5), Decision::Review(_)));
+
+        let candidates = super::rank("Unknown", &files(&["x.mkv"]), &animes, &names, &none, 0.8);
+        assert_eq!(decide(candidates, 0.5), Decision::Unmatched);
+
+        // 内容相同的文件和同名的文件一样计算.
+        let contents = HashMap::from([("true tears [真实之泪]".to_string(), files(&["x.mkv"]))]);
+        let candidates = super::rank(
+            "Unknown",
+            &files(&["x.mkv"]),
+            &animes,
+            &names,
+            &contents,
+            0.8,
+        );
+        let Decision::Matched(candidate) = decide(candidates, 0.5) else {
+            panic!("")
+        };
+        assert_eq!(candidate.anime, "true tears [真实之泪]");
+        assert_eq!(
+            candidate.reason,
+            MatchReason::SameContent {
+                files: vec!["x.mkv".to_string()]
+            }
+        );
+        let candidates = super::rank("NCOP.mkv", &files(&[]), &animes, &names, &none, 0.8);
+        assert_eq!(candidates.len(), 2);
+        assert!(matches!(decide(candidates, 0.5), Decision::Review(_)));
+    }
+}
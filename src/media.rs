@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+const DEFAULT_VIDEO: [&str; 15] = [
+    ".mkv", ".mp4", ".avi", ".m2ts", ".ts", ".webm", ".rmvb", ".rm", ".wmv", ".flv", ".mov",
+    ".m4v", ".mpg", ".mpeg", ".vob",
+];
+const DEFAULT_SUBTITLE: [&str; 7] = [".ass", ".ssa", ".srt", ".sup", ".sub", ".idx", ".vtt"];
+const DEFAULT_AUDIO: [&str; 11] = [
+    ".flac", ".mka", ".aac", ".ac3", ".dts", ".thd", ".mp3", ".m4a", ".opus", ".ogg", ".wav",
+];
+const DEFAULT_IMAGE: [&str; 8] = [
+    ".jpg", ".jpeg", ".png", ".webp", ".bmp", ".gif", ".tif", ".tiff",
+];
+const DEFAULT_FONT: [&str; 5] = [".ttf", ".otf", ".ttc", ".woff", ".woff2"];
+
+// 文件的媒体类别.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Video,    // 正片和特典.
+    Subtitle, // 外挂字幕.
+    Audio,    // 外挂音轨和 CD.
+    Image,    // 扫图和封面.
+    Font,     // 字幕字体.
+    Other,    // 其他文件, 比如 txt, url.
+}
+
+impl MediaKind {
+    pub const ALL: [MediaKind; 6] = [
+        MediaKind::Video,
+        MediaKind::Subtitle,
+        MediaKind::Audio,
+        MediaKind::Image,
+        MediaKind::Font,
+        MediaKind::Other,
+    ];
+}
+
+impl fmt::Display for MediaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use MediaKind::*;
+        match self {
+            Video => write!(f, "video"),
+            Subtitle => write!(f, "subtitle"),
+            Audio => write!(f, "audio"),
+            Image => write!(f, "image"),
+            Font => write!(f, "font"),
+            Other => write!(f, "other"),
+        }
+    }
+}
+
+// 按后缀分类文件的规则, 后缀不区分大小写.
+// 配置文件中未设置的类别使用默认值.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaRules {
+    pub video: Vec<String>,
+    pub subtitle: Vec<String>,
+    pub audio: Vec<String>,
+    pub image: Vec<String>,
+    pub font: Vec<String>,
+    pub link: Vec<MediaKind>, // 需要链接的类别, 其他类别的文件链接时跳过.
+}
+
+impl Default for MediaRules {
+    fn default() -> Self {
+        let to_vec = |x: &[&str]| x.iter().map(|x| x.to_string()).collect();
+        MediaRules {
+            video: to_vec(&DEFAULT_VIDEO),
+            subtitle: to_vec(&DEFAULT_SUBTITLE),
+            audio: to_vec(&DEFAULT_AUDIO),
+            image: to_vec(&DEFAULT_IMAGE),
+            font: to_vec(&DEFAULT_FONT),
+            link: MediaKind::ALL.to_vec(),
+        }
+    }
+}
+
+impl MediaRules {
+    // 多个后缀都匹配时最长的优先, 可以用 ".sc.ass" 这样的后缀覆盖默认分类.
+    pub fn classify(&self, name: &str) -> MediaKind {
+        self.matched(name).0
+    }
+
+    // 匹配的后缀, 保留文件名中的大小写. 没有匹配时为 None.
+    pub fn extension<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let len = self.matched(name).1;
+        if len == 0 {
+            return None;
+        }
+        // 匹配时比较的是小写, 长度变化时切不出来就放弃.
+        name.get(name.len().checked_sub(len)?..)
+    }
+
+    // 最长的匹配后缀的类别和长度.
+    fn matched(&self, name: &str) -> (MediaKind, usize) {
+        let name = name.to_lowercase();
+        let mut kind = MediaKind::Other;
+        let mut matched = 0;
+        for (k, extensions) in [
+            (MediaKind::Video, &self.video),
+            (MediaKind::Subtitle, &self.subtitle),
+            (MediaKind::Audio, &self.audio),
+            (MediaKind::Image, &self.image),
+            (MediaKind::Font, &self.font),
+        ] {
+            for extension in extensions {
+                if extension.len() > matched && name.ends_with(&extension.to_lowercase()) {
+                    kind = k;
+                    matched = extension.len();
+                }
+            }
+        }
+        (kind, matched)
+    }
+
+    pub fn is_video(&self, name: &str) -> bool {
+        self.classify(name) == MediaKind::Video
+    }
+
+    // 链接时是否包含这个文件.
+    pub fn should_link(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.link.contains(&self.classify(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let rules = MediaRules::default();
+        let kinds: Vec<_> = [
+            "[VCB-Studio] AIR [01].MKV",
+            "00000.m2ts",
+            "AIR [01].sc.ass",
+            "AIR [01].mka",
+            "Scans/01.webp",
+            "FZLTH.TTF",
+            "readme about WebP.txt",
+        ]
+        .iter()
+        .map(|x| rules.classify(x))
+        .collect();
+        assert_eq!(
+            kinds,
+            [
+                MediaKind::Video,
+                MediaKind::Video,
+                MediaKind::Subtitle,
+                MediaKind::Audio,
+                MediaKind::Image,
+                MediaKind::Font,
+                MediaKind::Other,
+            ]
+        );
+
+        let rules = MediaRules {
+            link: vec![MediaKind::Video, MediaKind::Subtitle],
+            ..Default::default()
+        };
+        assert_eq!(rules.extension("AIR [01].MKV"), Some(".MKV"));
+        assert_eq!(rules.extension("readme"), None);
+        assert!(rules.should_link(Path::new("a/b.ass")));
+        assert!(!rules.should_link(Path::new("a/b.txt")));
+    }
+}
@@ -0,0 +1,141 @@
+use serde_yaml::{Mapping, Value};
+
+// map 文件当前的版本.
+// 修改保存的结构 (RealData, SourceAnimeMap, FileMap, Rename) 时都要加一并添加迁移函数,
+// 包括新增有默认值的字段. 旧程序读取新版本的文件时会报错, 否则会丢掉不认识的字段后写回.
+pub const CURRENT_VERSION: u32 = 6;
+
+type Migration = fn(&mut Mapping) -> Result<(), String>;
+
+// 第 i 项把版本 i + 1 的文件迁移到版本 i + 2.
+const MIGRATIONS: [Migration; 5] = [v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6];
+
+// 文件的版本, 没有 version 字段的是版本 1.
+pub fn version_of(value: &Value) -> Result<u32, String> {
+    match value.get("version") {
+        None => Ok(1),
+        Some(version) => version
+            .as_u64()
+            .and_then(|x| u32::try_from(x).ok())
+            .ok_or(format!("invalid version: {:?}", version)),
+    }
+}
+
+// 把旧版本的文件迁移到当前版本, 返回原来的版本.
+pub fn migrate(value: &mut Value) -> Result<u32, String> {
+    let version = version_of(value)?;
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "version {} is newer than {}, please upgrade anime_reflink",
+            version, CURRENT_VERSION
+        ));
+    }
+    if version == 0 {
+        return Err("invalid version: 0".to_string());
+    }
+    let mapping = value
+        .as_mapping_mut()
+        .ok_or("the map file isn't a mapping")?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(mapping).map_err(|e| format!("migrate to version {} failed: {}", i + 2, e))?;
+        mapping.insert("version".into(), (i as u32 + 2).into());
+    }
+    Ok(version)
+}
+
+// 版本 1 没有 version 字段, root, link_modes 和 linked_by 都有默认值,
+// 只需要检查结构.
+fn v1_to_v2(mapping: &mut Mapping) -> Result<(), String> {
+    match mapping.get("source_anime_maps") {
+        None | Some(Value::Sequence(_)) => Ok(()),
+        Some(_) => Err("source_anime_maps isn't a list".to_string()),
+    }
+}
+
+// 版本 3 增加了 ignored_by, 没有时为 None, 不需要转换.
+fn v2_to_v3(_: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+// 版本 4 增加了单独映射的文件 files, 没有时按文件夹映射, 不需要转换.
+fn v3_to_v4(_: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+// 版本 5 增加了 sub_path, layout, season 和 destination.
+// 旧的 map 都是保持原样放到动漫文件夹下, 和默认的 nested 布局相同, 不需要转换.
+fn v4_to_v5(_: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+// 版本 6 增加了重命名模板 rename 和重命名记录 renamed, 旧的 map 没有重命名过.
+fn v5_to_v6(_: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::RealData, source_anime_map::Layout};
+
+    // 迁移到当前版本后读取.
+    fn load(content: &str) -> RealData {
+        let mut value: Value = serde_yaml::from_str(content).unwrap();
+        super::migrate(&mut value).unwrap();
+        assert_eq!(version_of(&value).unwrap(), CURRENT_VERSION);
+        serde_yaml::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn migrate() {
+        let mut value: Value = serde_yaml::from_str("source_anime_maps: []").unwrap();
+        assert_eq!(super::migrate(&mut value).unwrap(), 1);
+        assert_eq!(version_of(&value).unwrap(), CURRENT_VERSION);
+        assert_eq!(super::migrate(&mut value).unwrap(), CURRENT_VERSION);
+
+        let mut value: Value = serde_yaml::from_str("version: 99").unwrap();
+        assert!(super::migrate(&mut value).is_err());
+        let mut value: Value = serde_yaml::from_str("source_anime_maps: 1").unwrap();
+        assert!(super::migrate(&mut value).is_err());
+    }
+
+    #[test]
+    fn v2_to_v3() {
+        let data = load(
+            "version: 2\nsource_anime_maps:\n- source: a\n  anime: ''\n  active: true\n  file_type: Other\n  link_modes: [copy]\n",
+        );
+        assert_eq!(data.source_anime_maps[0].ignored_by, None);
+    }
+
+    #[test]
+    fn v3_to_v4() {
+        let data = load(
+            "version: 3\nsource_anime_maps:\n- source: a\n  anime: ''\n  active: true\n  file_type: Other\n  ignored_by: 'config: *.torrent'\n",
+        );
+        assert_eq!(data.source_anime_maps[0].files, []);
+        assert!(data.source_anime_maps[0].ignored_by.is_some());
+    }
+
+    #[test]
+    fn v4_to_v5() {
+        let data = load(
+            "version: 4\nsource_anime_maps:\n- source: a\n  anime: AIR\n  active: true\n  file_type: Dir\n  files:\n  - source: 01.mkv\n    include: false\n",
+        );
+        let map = &data.source_anime_maps[0];
+        assert_eq!(map.layout, Layout::Nested);
+        assert_eq!((map.sub_path.as_str(), map.season), ("", None));
+        assert!(map.destination.is_empty());
+        assert!(!map.files[0].include);
+    }
+
+    #[test]
+    fn v5_to_v6() {
+        let data = load(
+            "version: 5\nsource_anime_maps:\n- source: a\n  anime: AIR\n  active: false\n  file_type: Dir\n  layout: season\n  season: 2\n",
+        );
+        let map = &data.source_anime_maps[0];
+        assert_eq!((map.layout, map.season), (Layout::Season, Some(2)));
+        assert_eq!(map.rename, None);
+        assert!(map.renamed.is_empty());
+    }
+}
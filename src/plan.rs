@@ -0,0 +1,495 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    linker::LinkMode,
+    matcher::Candidate,
+    source_anime_map::{FileMap, FileType, MapPath, SourceAnimeMap},
+    store,
+};
+
+// 匹配到动漫的原因.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MatchReason {
+    Recorded,                            // map 中已经记录了动漫.
+    SourceName,                          // 动漫文件夹中有同名的文件或文件夹.
+    FileNames { files: Vec<String> },    // 源文件夹中的视频文件出现在动漫文件夹中.
+    SameContent { files: Vec<String> },  // 动漫文件夹中有内容相同但改了名的文件.
+    Title { title: String, score: f64 }, // 源名称和动漫名称相似.
+}
+
+impl fmt::Display for MatchReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchReason::Recorded => write!(f, "recorded in map file"),
+            MatchReason::SourceName => write!(f, "source name found in anime"),
+            MatchReason::FileNames { files } => {
+                write!(f, "video files found in anime: {}", files.join(", "))
+            }
+            MatchReason::SameContent { files } => {
+                write!(f, "same content found in anime: {}", files.join(", "))
+            }
+            MatchReason::Title { title, score } => {
+                write!(f, "title similar to {} (score {:.2})", title, score)
+            }
+        }
+    }
+}
+
+// 待链接的 map.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkTask {
+    pub path: MapPath, // map 的地址, 计划后新增的 map 不影响它.
+    pub anime: String,
+    pub reason: MatchReason,
+}
+
+// 计划文件中的任务, 记录计划时 map 的设置和源, 目标的状态.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedTask {
+    #[serde(flatten)]
+    pub task: LinkTask,
+    pub map: SourceAnimeMap,
+    pub source_state: Snapshot,
+    pub destination_state: Snapshot,
+}
+
+// 计划文件, 审阅后再执行.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PlanFile {
+    pub created: String,
+    pub tasks: Vec<PlannedTask>,
+}
+
+impl PlanFile {
+    pub fn from_path(path: &Path) -> Result<PlanFile, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("read plan {} failed: {}", path.display(), e))?;
+        let plan = serde_yaml::from_str(&content)
+            .map_err(|e| format!("parse plan {} failed: {}", path.display(), e))?;
+        Ok(plan)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        store::write_atomic(path, serde_yaml::to_string(self)?.as_bytes())
+            .map_err(|e| format!("write plan {} failed: {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+// 一个文件的大小和修改时间.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub path: String, // 相对路径.
+    pub size: u64,
+    pub modified: u64, // 纳秒.
+}
+
+// 一棵目录树中所有文件的状态, 不存在时为空.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub files: Vec<FileState>,
+}
+
+impl Snapshot {
+    pub fn take(path: &Path) -> io::Result<Snapshot> {
+        let mut snapshot = Snapshot::default();
+        match fs::symlink_metadata(path) {
+            Ok(_) => snapshot.push(path, Path::new(""))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        snapshot.files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(snapshot)
+    }
+
+    fn push(&mut self, path: &Path, relative: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_dir() {
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                self.push(&entry.path(), &relative.join(entry.file_name()))?;
+            }
+            return Ok(());
+        }
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos() as u64)
+            .unwrap_or_default();
+        self.files.push(FileState {
+            path: relative.to_string_lossy().to_string(),
+            size: metadata.len(),
+            modified,
+        });
+        Ok(())
+    }
+
+    // 返回第一个不同的文件, 用于错误信息.
+    pub fn first_difference(&self, other: &Snapshot) -> Option<String> {
+        let find = |a: &Snapshot, b: &Snapshot| {
+            a.files
+                .iter()
+                .find(|x| !b.files.contains(x))
+                .map(|x| x.path.clone())
+        };
+        find(self, other).or_else(|| find(other, self))
+    }
+}
+
+// 新发现的源.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct NewSource {
+    pub root: String,
+    pub source: String,
+    pub file_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignored_by: Option<String>,
+}
+
+// 匹配结果.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Match {
+    pub root: String,
+    pub source: String,
+    pub anime: String,
+    pub reason: MatchReason,
+}
+
+// 无法确定动漫的源和它的候选.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Review {
+    pub root: String,
+    pub source: String,
+    pub candidates: Vec<Candidate>,
+}
+
+// 只列出前三个候选.
+impl fmt::Display for Review {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    [{}] {}", self.root, self.source)?;
+        for candidate in self.candidates.iter().take(3) {
+            writeln!(
+                f,
+                "        {} (confidence {:.2}): {}",
+                candidate.anime, candidate.confidence, candidate.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// 将要执行的链接.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Operation {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub link_modes: Vec<LinkMode>,
+}
+
+// 将要执行的重命名.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Renaming {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+// map 文件中将要发生的变化.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Change {
+    pub root: String,
+    pub source: String, // 嵌套的源用 "Collection/Series/Season" 表示.
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+// 只读运行的结果.
+#[derive(Serialize, Debug, Default)]
+pub struct Plan {
+    pub new_sources: Vec<NewSource>,
+    pub matches: Vec<Match>,
+    pub reviews: Vec<Review>,
+    pub operations: Vec<Operation>,
+    pub renames: Vec<Renaming>,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // 对比运行前后的 map, 记录新增的源和字段的变化.
+    pub fn diff_maps(&mut self, before: &[SourceAnimeMap], after: &[SourceAnimeMap]) {
+        diff_maps(before, after, "", &mut self.changes);
+        self.new_sources = after
+            .iter()
+            .filter(|x| {
+                !before
+                    .iter()
+                    .any(|y| y.root == x.root && y.source == x.source)
+            })
+            .map(|x| NewSource {
+                root: x.root.clone(),
+                source: x.source.clone(),
+                file_type: file_type_name(&x.file_type).to_string(),
+                ignored_by: x.ignored_by.clone(),
+            })
+            .collect();
+    }
+}
+
+// 两组 map 之间字段的变化.
+pub fn changes(before: &[SourceAnimeMap], after: &[SourceAnimeMap]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_maps(before, after, "", &mut changes);
+    changes
+}
+
+fn diff_maps(
+    before: &[SourceAnimeMap],
+    after: &[SourceAnimeMap],
+    parent: &str,
+    changes: &mut Vec<Change>,
+) {
+    for map in after {
+        let source = match parent.is_empty() {
+            true => map.source.clone(),
+            false => format!("{}/{}", parent, map.source),
+        };
+        let mut push = |field: &str, before: String, after: String| {
+            if before != after {
+                changes.push(Change {
+                    root: map.root.clone(),
+                    source: source.clone(),
+                    field: field.to_string(),
+                    before,
+                    after,
+                });
+            }
+        };
+        let Some(old) = before
+            .iter()
+            .find(|x| x.root == map.root && x.source == map.source)
+        else {
+            push("map", "".to_string(), "added".to_string());
+            continue;
+        };
+        push("anime", old.anime.clone(), map.anime.clone());
+        push("active", old.active.to_string(), map.active.to_string());
+        push(
+            "file_type",
+            file_type_name(&old.file_type).to_string(),
+            file_type_name(&map.file_type).to_string(),
+        );
+        let linked_by = |x: &SourceAnimeMap| x.linked_by.map(|x| x.to_string()).unwrap_or_default();
+        push("linked_by", linked_by(old), linked_by(map));
+        let link_modes = |x: &SourceAnimeMap| {
+            let modes: Vec<_> = x.link_modes.iter().map(|x| x.to_string()).collect();
+            modes.join(",")
+        };
+        push("link_modes", link_modes(old), link_modes(map));
+        push(
+            "ignored_by",
+            old.ignored_by.clone().unwrap_or_default(),
+            map.ignored_by.clone().unwrap_or_default(),
+        );
+        push("sub_path", old.sub_path.clone(), map.sub_path.clone());
+        push("layout", old.layout.to_string(), map.layout.to_string());
+        push(
+            "destination",
+            old.destination.clone(),
+            map.destination.clone(),
+        );
+        push(
+            "renamed",
+            old.renamed.len().to_string(),
+            map.renamed.len().to_string(),
+        );
+        let season = |x: &SourceAnimeMap| x.season.map(|x| x.to_string()).unwrap_or_default();
+        push("season", season(old), season(map));
+        // 为空时使用全局模板, 空字符串是不重命名.
+        let rename = |x: &SourceAnimeMap| match x.rename.as_deref() {
+            None => String::new(),
+            Some("") => "disabled".to_string(),
+            Some(template) => template.to_string(),
+        };
+        push("rename", rename(old), rename(map));
+        // 单独映射的文件按源路径逐个比较.
+        let mut files: Vec<_> = old
+            .files
+            .iter()
+            .chain(&map.files)
+            .map(|x| &x.source)
+            .collect();
+        files.sort();
+        files.dedup();
+        for file in files {
+            let state = |x: &SourceAnimeMap| {
+                x.files
+                    .iter()
+                    .find(|x| &x.source == file)
+                    .map(file_state)
+                    .unwrap_or_default()
+            };
+            push(&format!("files/{}", file), state(old), state(map));
+        }
+
+        if let (FileType::Nesting(old), FileType::Nesting(new)) = (&old.file_type, &map.file_type) {
+            diff_maps(old, new, &source, changes);
+        }
+    }
+}
+
+fn file_state(file: &FileMap) -> String {
+    let destination = match (file.include, file.destination.as_str()) {
+        (false, _) => "excluded".to_string(),
+        (true, "") => "with folder".to_string(),
+        (true, destination) => destination.to_string(),
+    };
+    match file.linked_by {
+        Some(mode) => format!("{} ({})", destination, mode),
+        None => destination,
+    }
+}
+
+fn file_type_name(file_type: &FileType) -> &'static str {
+    match file_type {
+        FileType::File => "file",
+        FileType::Dir => "dir",
+        FileType::Other => "other",
+        FileType::Nesting(_) => "nesting",
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "New sources ({}):", self.new_sources.len())?;
+        for x in &self.new_sources {
+            match &x.ignored_by {
+                Some(rule) => writeln!(
+                    f,
+                    "    [{}] {} ({}, ignored by {})",
+                    x.root, x.source, x.file_type, rule
+                )?,
+                None => writeln!(f, "    [{}] {} ({})", x.root, x.source, x.file_type)?,
+            }
+        }
+        writeln!(f, "Matches ({}):", self.matches.len())?;
+        for x in &self.matches {
+            writeln!(f, "    [{}] {} -> {}", x.root, x.source, x.anime)?;
+            writeln!(f, "        {}", x.reason)?;
+        }
+        writeln!(f, "Needs review ({}):", self.reviews.len())?;
+        for x in &self.reviews {
+            write!(f, "{}", x)?;
+        }
+        writeln!(f, "Operations ({}):", self.operations.len())?;
+        for x in &self.operations {
+            let modes: Vec<_> = x.link_modes.iter().map(|x| x.to_string()).collect();
+            writeln!(f, "    {} ({})", x.source.display(), modes.join(", "))?;
+            writeln!(f, "        -> {}", x.destination.display())?;
+        }
+        writeln!(f, "Renames ({}):", self.renames.len())?;
+        for x in &self.renames {
+            writeln!(f, "    {}", x.from.display())?;
+            writeln!(f, "        -> {}", x.to.display())?;
+        }
+        writeln!(f, "Map file changes ({}):", self.changes.len())?;
+        for x in &self.changes {
+            writeln!(
+                f,
+                "    [{}] {} {}: {:?} -> {:?}",
+                x.root, x.source, x.field, x.before, x.after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn snapshot() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let path = tep_dir.path().join("source");
+        assert_eq!(Snapshot::take(&path).unwrap(), Snapshot::default());
+
+        fs::create_dir_all(path.join("Season 01")).unwrap();
+        fs::write(path.join("Season 01").join("b.mkv"), b"b").unwrap();
+        fs::write(path.join("a.mkv"), b"a").unwrap();
+        let before = Snapshot::take(&path).unwrap();
+        let paths: Vec<_> = before.files.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(paths, ["Season 01/b.mkv", "a.mkv"]);
+        assert_eq!(
+            before.first_difference(&Snapshot::take(&path).unwrap()),
+            None
+        );
+
+        fs::write(path.join("a.mkv"), b"aa").unwrap();
+        let after = Snapshot::take(&path).unwrap();
+        assert_eq!(before.first_difference(&after), Some("a.mkv".to_string()));
+    }
+
+    #[test]
+    fn diff_maps() {
+        let map = |source: &str, anime: &str, file_type: FileType| SourceAnimeMap {
+            source: source.to_string(),
+            anime: anime.to_string(),
+            active: true,
+            file_type,
+            ..Default::default()
+        };
+        let before = vec![map(
+            "nesting",
+            "",
+            FileType::Nesting(vec![map("child", "", FileType::Dir)]),
+        )];
+        let mut child = map("child", "AIR", FileType::Dir);
+        child.season = Some(2);
+        child.rename = Some("".to_string());
+        child.file_mut("01.mkv").include = false;
+        let after = vec![
+            map("nesting", "AIR", FileType::Nesting(vec![child])),
+            map("new", "", FileType::File),
+        ];
+
+        let mut plan = Plan::default();
+        plan.diff_maps(&before, &after);
+        assert_eq!(
+            plan.new_sources,
+            [NewSource {
+                root: "".to_string(),
+                source: "new".to_string(),
+                file_type: "file".to_string(),
+                ignored_by: None,
+            }]
+        );
+        let changes: Vec<_> = plan
+            .changes
+            .iter()
+            .map(|x| (x.source.as_str(), x.field.as_str(), x.after.as_str()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("nesting", "anime", "AIR"),
+                ("nesting/child", "anime", "AIR"),
+                ("nesting/child", "season", "2"),
+                ("nesting/child", "rename", "disabled"),
+                ("nesting/child", "files/01.mkv", "excluded"),
+                ("new", "map", "added"),
+            ]
+        );
+    }
+}
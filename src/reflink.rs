@@ -0,0 +1,235 @@
+use std::{
+    fs::{self, File, FileTimes, Metadata},
+    io,
+    path::Path,
+};
+
+// 使用 FICLONE 克隆整个文件, 目标文件已存在时会被覆盖.
+// 文件系统要求克隆的范围按块对齐时 FICLONE 返回 EINVAL,
+// 这时用 FICLONERANGE 克隆对齐的部分, 结尾不满一块的部分复制.
+#[cfg(target_os = "linux")]
+pub fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let mut source_file = File::open(source)?;
+    let mut target_file = File::create(target)?;
+    // SAFETY: 两个 fd 在调用期间都有效, FICLONE 只读取 source fd.
+    let ret = unsafe {
+        libc::ioctl(
+            target_file.as_raw_fd(),
+            libc::FICLONE,
+            source_file.as_raw_fd(),
+        )
+    };
+    let result = match ret {
+        -1 => match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::EINVAL) => {
+                clone_aligned(&mut source_file, &mut target_file)
+            }
+            e => Err(e),
+        },
+        _ => Ok(()),
+    };
+    if let Err(error) = result {
+        // 不留下空文件或者只有一部分的文件.
+        drop(target_file);
+        let _ = fs::remove_file(target);
+        return Err(error);
+    }
+    Ok(())
+}
+
+// 克隆按块对齐的部分, 再复制剩下的结尾.
+#[cfg(target_os = "linux")]
+fn clone_aligned(source: &mut File, target: &mut File) -> io::Result<()> {
+    use std::{
+        io::{Seek, SeekFrom},
+        os::{fd::AsRawFd, unix::fs::MetadataExt},
+    };
+
+    let metadata = source.metadata()?;
+    let aligned = metadata.len() / metadata.blksize().max(1) * metadata.blksize().max(1);
+    // 不满一块时没有可以共享的部分, 交给回退链中的其他方式.
+    if aligned == 0 {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let range = libc::file_clone_range {
+        src_fd: source.as_raw_fd() as i64,
+        src_offset: 0,
+        src_length: aligned,
+        dest_offset: 0,
+    };
+    // SAFETY: range 在调用期间有效, 其中的 fd 也有效.
+    let ret = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONERANGE, &range) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    source.seek(SeekFrom::Start(aligned))?;
+    target.seek(SeekFrom::Start(aligned))?;
+    io::copy(source, target)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn clone_file(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink only supports linux",
+    ))
+}
+
+// 复制权限, 访问/修改时间和扩展属性.
+// 返回没有权限复制的扩展属性, 它们不影响链接的结果.
+pub fn copy_metadata(source: &Path, target: &Path, metadata: &Metadata) -> io::Result<Vec<String>> {
+    let skipped = copy_xattrs(source, target)?;
+    fs::set_permissions(target, metadata.permissions())?;
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::open(target)?.set_times(times)?;
+    Ok(skipped)
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(source: &Path, target: &Path) -> io::Result<Vec<String>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let to_cstring = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let source = to_cstring(source)?;
+    let target = to_cstring(target)?;
+
+    // 先取长度再取内容.
+    // SAFETY: 所有指针都指向存活的缓冲区, 长度与缓冲区一致.
+    let len = unsafe { libc::llistxattr(source.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        return ignore_unsupported(io::Error::last_os_error()).map(|_| Vec::new());
+    }
+    let mut names = vec![0u8; len as usize];
+    let len = unsafe {
+        libc::llistxattr(
+            source.as_ptr(),
+            names.as_mut_ptr() as *mut libc::c_char,
+            names.len(),
+        )
+    };
+    if len < 0 {
+        return ignore_unsupported(io::Error::last_os_error()).map(|_| Vec::new());
+    }
+    names.truncate(len as usize);
+
+    let mut skipped = Vec::new();
+    for name in names.split(|b| *b == 0).filter(|name| !name.is_empty()) {
+        let name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let size =
+            unsafe { libc::lgetxattr(source.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe {
+            libc::lgetxattr(
+                source.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ret = unsafe {
+            libc::lsetxattr(
+                target.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                size as usize,
+                0,
+            )
+        };
+        if ret < 0 {
+            let error = io::Error::last_os_error();
+            let name = name.to_string_lossy().to_string();
+            // 普通用户不能写入 security 和 trusted 命名空间, 数据已经链接, 只记录下来.
+            let privileged = name.starts_with("security.") || name.starts_with("trusted.");
+            match error.raw_os_error() {
+                Some(libc::EPERM) if privileged => skipped.push(name),
+                _ => ignore_unsupported(error)?,
+            }
+        }
+    }
+    Ok(skipped)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_: &Path, _: &Path) -> io::Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+// 文件系统不支持扩展属性时不算错误.
+#[cfg(target_os = "linux")]
+fn ignore_unsupported(error: io::Error) -> io::Result<()> {
+    match error.raw_os_error() {
+        Some(libc::ENOTSUP) => Ok(()),
+        _ => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn clone_file() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let source = tep_dir.path().join("a.mkv");
+        let target = tep_dir.path().join("b.mkv");
+        fs::write(&source, b"a").unwrap();
+
+        // 不支持 reflink 的文件系统上必须返回错误, 并且不留下空文件.
+        match super::clone_file(&source, &target) {
+            Ok(_) => {
+                let metadata = fs::metadata(&source).unwrap();
+                assert!(copy_metadata(&source, &target, &metadata)
+                    .unwrap()
+                    .is_empty());
+                assert_eq!(fs::read(&target).unwrap(), b"a");
+                assert_eq!(
+                    metadata.modified().unwrap(),
+                    fs::metadata(&target).unwrap().modified().unwrap()
+                );
+            }
+            Err(_) => assert!(!target.exists()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn clone_aligned() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tep_dir = tempdir_in("./").unwrap();
+        let source = tep_dir.path().join("a.mkv");
+        let target = tep_dir.path().join("b.mkv");
+        let block = fs::metadata(tep_dir.path()).unwrap().blksize() as usize;
+        let content: Vec<u8> = (0..block * 2 + 5).map(|x| x as u8).collect();
+        fs::write(&source, &content).unwrap();
+
+        // 克隆对齐的部分后复制结尾, 不支持时返回错误.
+        let mut source_file = File::open(&source).unwrap();
+        let mut target_file = File::create(&target).unwrap();
+        if super::clone_aligned(&mut source_file, &mut target_file).is_ok() {
+            drop(target_file);
+            assert_eq!(fs::read(&target).unwrap(), content);
+        }
+
+        // 不满一块时交给其他方式.
+        fs::write(&source, b"a").unwrap();
+        let mut source_file = File::open(&source).unwrap();
+        let mut target_file = File::create(&target).unwrap();
+        assert!(super::clone_aligned(&mut source_file, &mut target_file).is_err());
+    }
+}
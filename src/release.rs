@@ -0,0 +1,433 @@
+use regex::Regex;
+use serde::Serialize;
+use std::{ops::RangeInclusive, sync::OnceLock};
+
+// 语言标记中可能出现的汉字, 比如 "简日双语", "繁体内封".
+const LANGUAGE_CHARS: &str = "简繁日中英双语体字幕内嵌封外挂";
+const LANGUAGES: [&str; 14] = [
+    "chs", "cht", "sc", "tc", "gb", "big5", "jp", "jpn", "jap", "eng", "en", "chi", "zh", "ja",
+];
+
+// 片源.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Bd,
+    Web,
+    Tv,
+    Dvd,
+}
+
+// 从字幕组和 BD 压制的发布名称中识别的信息, 识别不到的为 None.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Release {
+    pub group: Option<String>,
+    pub title: Option<String>,
+    pub season: Option<u32>,
+    pub episodes: Option<RangeInclusive<u32>>, // 单集时开始和结束相同.
+    pub version: Option<u32>,                  // v2 等修正版本.
+    pub resolution: Option<String>,            // 统一为 "1080p" 的形式.
+    pub codec: Option<String>,                 // 统一为 "x265", "HEVC" 等.
+    pub source: Option<Source>,
+    pub crc32: Option<String>, // 大写.
+    pub languages: Vec<String>,
+}
+
+// 名称中的片段: 括号中的标签和括号外的文本.
+enum Segment<'a> {
+    Tag(&'a str),
+    Text(&'a str),
+}
+
+impl Release {
+    // name 可以带后缀, 字幕的语言后缀会记录到 languages.
+    pub fn parse(name: &str) -> Release {
+        let mut release = Release::default();
+        let name = release.strip_extension(name.trim());
+        let segments = split(name);
+
+        let mut rest = segments.as_slice();
+        // 开头的标签是字幕组.
+        if let [Segment::Tag(group), tail @ ..] = rest {
+            if !tail.is_empty() {
+                release.group = Some(group.trim().to_string());
+                rest = tail;
+            }
+        }
+        let mut title_tags = Vec::new();
+        let mut parsed_text = false;
+        for segment in rest {
+            match segment {
+                Segment::Text(text) if !parsed_text && !text.trim().is_empty() => {
+                    release.parse_text(text);
+                    parsed_text = true;
+                }
+                Segment::Text(text) => release.parse_words(text),
+                Segment::Tag(tag) => {
+                    if !release.parse_tag(tag) {
+                        title_tags.push(*tag);
+                    }
+                }
+            }
+        }
+        // "[字幕组][标题][05][1080p]" 形式的标题在标签中.
+        if release.title.is_none() {
+            if let Some(tag) = title_tags.first() {
+                release.parse_text(tag);
+            }
+        }
+        release
+    }
+
+    // 第一集, 用于重命名和匹配.
+    pub fn episode(&self) -> Option<u32> {
+        self.episodes.as_ref().map(|x| *x.start())
+    }
+
+    // 去掉后缀, 字幕的语言后缀比如 ".sc.ass" 的 "sc" 记录为语言.
+    fn strip_extension<'a>(&mut self, name: &'a str) -> &'a str {
+        static EXTENSION: OnceLock<Regex> = OnceLock::new();
+        let regex = EXTENSION.get_or_init(|| Regex::new(r"\.[A-Za-z][A-Za-z0-9]{1,4}$").unwrap());
+        let Some(m) = regex.find(name) else {
+            return name;
+        };
+        let name = &name[..m.start()];
+        match name.rsplit_once('.') {
+            Some((rest, tag)) if is_language(tag) => {
+                self.languages.push(tag.to_uppercase());
+                rest
+            }
+            _ => name,
+        }
+    }
+
+    // 括号外的文本: 标题, 季度和集数, 集数后面的是其他标签.
+    fn parse_text(&mut self, text: &str) {
+        static REGEXES: OnceLock<[Regex; 6]> = OnceLock::new();
+        let regexes = REGEXES.get_or_init(|| {
+            [
+                r"(?i)\bS(\d{1,2})E(\d{1,4})(?:v(\d))?(?:-E?(\d{1,4}))?\b",
+                r"\s-\s(\d{1,4})(?:v(\d))?(?:\s*[-~]\s*(\d{1,4})(?:v\d)?)?(?:\s|$)",
+                r"第(\d{1,4})(?:[-~](\d{1,4}))?[话話集]",
+                r"(?i)\bEP?(\d{1,4})(?:v(\d))?\b",
+                r"\s(\d{1,3})(?:v(\d))?(?:\s*[-~]\s*(\d{1,3}))?$",
+                r"(?i)\s(\d{1,3})(?:v(\d))?\s+(?:END|Fin)$",
+            ]
+            .map(|x| Regex::new(x).unwrap())
+        });
+
+        // 场景发布的名称用点分隔, 比如 "Title.S01E05.1080p.WEB-DL.x264-GROUP".
+        let scene;
+        let mut text = text.trim();
+        if !text.contains(' ') && text.matches('.').count() >= 2 {
+            scene = text.replace('.', " ");
+            text = &scene;
+            if let Some((rest, group)) = text.rsplit_once('-') {
+                if self.group.is_none() && !group.is_empty() && !group.contains(' ') {
+                    self.group = Some(group.to_string());
+                    text = rest;
+                }
+            }
+        }
+
+        let mut title_end = text.len();
+        for (i, regex) in regexes.iter().enumerate() {
+            let Some(captures) = regex.captures(text) else {
+                continue;
+            };
+            let number = |i: usize| captures.get(i).and_then(|x| x.as_str().parse().ok());
+            let (start, version, end) = match i {
+                0 => {
+                    self.season = number(1);
+                    (number(2), number(3), number(4))
+                }
+                2 => (number(1), None, number(2)),
+                _ => (number(1), number(2), number(3)),
+            };
+            let Some(start) = start else {
+                continue;
+            };
+            self.episodes = Some(start..=end.unwrap_or(start).max(start));
+            self.version = self.version.or(version);
+            let m = captures.get(0).unwrap();
+            title_end = m.start();
+            self.parse_words(&text[m.end()..]);
+            break;
+        }
+
+        let (title, season) = split_season(text[..title_end].trim().trim_end_matches('-'));
+        self.season = self.season.or(season);
+        let title = title.trim();
+        if self.title.is_none() && !title.is_empty() {
+            self.title = Some(title.to_string());
+        }
+    }
+
+    // 括号中的标签, 识别出任何信息时返回 true.
+    fn parse_tag(&mut self, tag: &str) -> bool {
+        static EPISODE: OnceLock<Regex> = OnceLock::new();
+        let regex = EPISODE.get_or_init(|| {
+            Regex::new(r"(?i)^(\d{1,4})(?:v(\d))?(?:\s*[-~]\s*(\d{1,4}))?(?:\s*(?:END|Fin))?$")
+                .unwrap()
+        });
+        let tag = tag.trim();
+        if tag.len() == 8 && tag.chars().all(|x| x.is_ascii_hexdigit()) && self.crc32.is_none() {
+            self.crc32 = Some(tag.to_uppercase());
+            return true;
+        }
+        if let Some(captures) = regex.captures(tag) {
+            let number = |i: usize| captures.get(i).and_then(|x| x.as_str().parse().ok());
+            // 四位数的标签更可能是年份.
+            if let (None, Some(start)) = (&self.episodes, number(1)) {
+                if !(1900..2100).contains(&start) {
+                    self.episodes = Some(start..=number(3).unwrap_or(start).max(start));
+                    self.version = self.version.or(number(2));
+                    return true;
+                }
+            }
+        }
+        let before = self.clone();
+        self.parse_words(tag);
+        *self != before || is_language(tag)
+    }
+
+    // 技术标签: 分辨率, 编码, 片源, 语言和版本.
+    fn parse_words(&mut self, text: &str) {
+        let separators = |x: char| x.is_whitespace() || "_,&+/".contains(x);
+        for word in text.split(separators).filter(|x| !x.is_empty()) {
+            if !self.parse_word(word) {
+                // "HEVC-10bit" 这样的组合.
+                word.split('-').for_each(|x| {
+                    self.parse_word(x);
+                });
+            }
+        }
+    }
+
+    fn parse_word(&mut self, word: &str) -> bool {
+        static REGEXES: OnceLock<[Regex; 3]> = OnceLock::new();
+        let [resolution, size, version] = REGEXES.get_or_init(|| {
+            [
+                r"(?i)^(\d{3,4})([pi])$",
+                r"(?i)^\d{3,4}x(\d{3,4})$",
+                r"(?i)^v(\d)$",
+            ]
+            .map(|x| Regex::new(x).unwrap())
+        });
+        let lower = word.to_lowercase();
+        if let Some(captures) = resolution.captures(word) {
+            let resolution = format!("{}{}", &captures[1], captures[2].to_lowercase());
+            self.resolution.get_or_insert(resolution);
+        } else if let Some(captures) = size.captures(word) {
+            self.resolution.get_or_insert(format!("{}p", &captures[1]));
+        } else if ["4k", "uhd"].contains(&lower.as_str()) {
+            self.resolution.get_or_insert("2160p".to_string());
+        } else if let Some(codec) = codec(&lower) {
+            self.codec.get_or_insert(codec.to_string());
+        } else if let Some(source) = source(&lower) {
+            self.source.get_or_insert(source);
+        } else if let Some(captures) = version.captures(word) {
+            self.version = self.version.or(captures[1].parse().ok());
+        } else if is_language(word) {
+            if !self.languages.iter().any(|x| x.eq_ignore_ascii_case(word)) {
+                self.languages.push(word.to_uppercase());
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+// 按括号切分, 支持 []、() 和【】, 不支持嵌套.
+fn split(name: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut open: Option<usize> = None;
+    for (i, c) in name.char_indices() {
+        match (c, open) {
+            ('[' | '(' | '【', None) => {
+                if i > start {
+                    segments.push(Segment::Text(&name[start..i]));
+                }
+                open = Some(i + c.len_utf8());
+            }
+            (']' | ')' | '】', Some(begin)) => {
+                segments.push(Segment::Tag(&name[begin..i]));
+                open = None;
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    match open {
+        // 没有闭合的括号当作文本.
+        Some(begin) => segments.push(Segment::Text(&name[begin..])),
+        None if start < name.len() => segments.push(Segment::Text(&name[start..])),
+        None => {}
+    }
+    segments
+}
+
+// 标题末尾的季度标记, 比如 "AIR S2", "Kanon Season 2", "Kanon 2nd Season".
+fn split_season(title: &str) -> (&str, Option<u32>) {
+    static SEASON: OnceLock<Regex> = OnceLock::new();
+    let regex = SEASON.get_or_init(|| {
+        Regex::new(
+            r"(?i)\s+(?:S\d{1,2}|Season\s*\d{1,2}|\d{1,2}(?:st|nd|rd|th)\s+Season|第\s*\S{1,3}\s*季)$",
+        )
+        .unwrap()
+    });
+    match regex.find(title) {
+        Some(m) => (title[..m.start()].trim(), season_of(m.as_str())),
+        None => (title, None),
+    }
+}
+
+// 从名称中识别季度, 比如 "S2", "Season 02", "2nd Season", "第2季", "第二季".
+pub fn season_of(name: &str) -> Option<u32> {
+    static SEASON: OnceLock<Regex> = OnceLock::new();
+    let regex = SEASON.get_or_init(|| {
+        Regex::new(
+            r"(?i)(?:\bseason\s*|\bs)(\d{1,2})\b|\b(\d{1,2})(?:st|nd|rd|th)\s+season\b|第\s*(\d{1,2}|[一二三四五六七八九十]+)\s*季",
+        )
+        .unwrap()
+    });
+    let captures = regex.captures(name)?;
+    let number = captures
+        .get(1)
+        .or(captures.get(2))
+        .or(captures.get(3))?
+        .as_str();
+    number.parse().ok().or_else(|| chinese_number(number))
+}
+
+// 只支持一到九十九.
+fn chinese_number(s: &str) -> Option<u32> {
+    let digit = |c: char| {
+        "一二三四五六七八九"
+            .chars()
+            .position(|x| x == c)
+            .map(|x| x as u32 + 1)
+    };
+    let chars: Vec<char> = s.chars().collect();
+    match chars.as_slice() {
+        ['十'] => Some(10),
+        ['十', b] => Some(10 + digit(*b)?),
+        [a, '十'] => Some(digit(*a)? * 10),
+        [a, '十', b] => Some(digit(*a)? * 10 + digit(*b)?),
+        [a] => digit(*a),
+        _ => None,
+    }
+}
+
+fn codec(word: &str) -> Option<&'static str> {
+    match word {
+        "x264" => Some("x264"),
+        "x265" => Some("x265"),
+        "h264" | "h.264" | "avc" => Some("AVC"),
+        "h265" | "h.265" | "hevc" => Some("HEVC"),
+        "av1" => Some("AV1"),
+        "vp9" => Some("VP9"),
+        _ => None,
+    }
+}
+
+fn source(word: &str) -> Option<Source> {
+    match word {
+        "bd" | "bdrip" | "bluray" | "blu-ray" | "bdmv" | "bdremux" => Some(Source::Bd),
+        "web" | "web-dl" | "webdl" | "webrip" | "web-rip" => Some(Source::Web),
+        "tv" | "tvrip" | "hdtv" => Some(Source::Tv),
+        "dvd" | "dvdrip" => Some(Source::Dvd),
+        _ => None,
+    }
+}
+
+fn is_language(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    LANGUAGES.contains(&lower.as_str())
+        || (word.chars().all(|x| LANGUAGE_CHARS.contains(x))
+            && word.chars().any(|x| "简繁日中英".contains(x)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let release = Release::parse("[VCB-Studio] Kimi no Na wa [Ma10p_1080p][x265_flac].mkv");
+        assert_eq!(
+            release,
+            Release {
+                group: Some("VCB-Studio".to_string()),
+                title: Some("Kimi no Na wa".to_string()),
+                resolution: Some("1080p".to_string()),
+                codec: Some("x265".to_string()),
+                ..Default::default()
+            }
+        );
+
+        let release =
+            Release::parse("[Nekomoe kissaten] Just Because! - 05v2 [WebRip 1080p HEVC-10bit AAC][CHS&JPN][A1B2C3D4].sc.ass");
+        assert_eq!(release.group.as_deref(), Some("Nekomoe kissaten"));
+        assert_eq!(release.title.as_deref(), Some("Just Because!"));
+        assert_eq!(release.episodes, Some(5..=5));
+        assert_eq!(release.version, Some(2));
+        assert_eq!(release.source, Some(Source::Web));
+        assert_eq!(release.codec.as_deref(), Some("HEVC"));
+        assert_eq!(release.crc32.as_deref(), Some("A1B2C3D4"));
+        assert_eq!(release.languages, ["SC", "CHS", "JPN"]);
+
+        let release = Release::parse("[SweetSub][AIR S2][03][BDRip][1080P][简日双语].mp4");
+        assert_eq!(release.title.as_deref(), Some("AIR"));
+        assert_eq!(release.season, Some(2));
+        assert_eq!(release.episode(), Some(3));
+        assert_eq!(release.source, Some(Source::Bd));
+        assert_eq!(release.languages, ["简日双语"]);
+
+        let release = Release::parse("[Group] true tears - 01-13 [BD 1920x1080 AVC]");
+        assert_eq!(release.episodes, Some(1..=13));
+        assert_eq!(release.resolution.as_deref(), Some("1080p"));
+
+        let release = Release::parse("Kanon.2006.S01E05.1080p.WEB-DL.x264-GROUP.mkv");
+        assert_eq!(release.group.as_deref(), Some("GROUP"));
+        assert_eq!(release.title.as_deref(), Some("Kanon 2006"));
+        assert_eq!((release.season, release.episode()), (Some(1), Some(5)));
+        assert_eq!(release.source, Some(Source::Web));
+
+        let release = Release::parse("CLANNAD 2nd Season 第05话");
+        assert_eq!(release.title.as_deref(), Some("CLANNAD"));
+        assert_eq!((release.season, release.episode()), (Some(2), Some(5)));
+    }
+
+    #[test]
+    fn season_of() {
+        let seasons: Vec<_> = [
+            "S2",
+            "[VCB-Studio] AIR S02 [Ma10p_1080p]",
+            "Season 3",
+            "2nd Season",
+            "第2季",
+            "第十二季",
+            "AIR",
+            "Specials",
+        ]
+        .iter()
+        .map(|x| super::season_of(x))
+        .collect();
+        assert_eq!(
+            seasons,
+            [
+                Some(2),
+                Some(2),
+                Some(3),
+                Some(2),
+                Some(2),
+                Some(12),
+                None,
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{media::MediaRules, release::Release};
+
+// 模板中的变量.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    Title,   // 从文件名中识别的标题.
+    Anime,   // 匹配到的动漫名称.
+    Season,  // 季度, 识别不到时使用 map 的季度.
+    Episode, // 集数.
+    Ext,     // 后缀, 字幕包括语言标记, 比如 ".sc.ass".
+    Name,    // 原文件名去掉后缀.
+}
+
+impl Var {
+    fn new(name: &str) -> Option<Var> {
+        match name {
+            "title" => Some(Var::Title),
+            "anime" => Some(Var::Anime),
+            "season" => Some(Var::Season),
+            "episode" => Some(Var::Episode),
+            "ext" => Some(Var::Ext),
+            "name" => Some(Var::Name),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Var(Var, usize), // 变量和数字补零后的宽度.
+}
+
+// 重命名模板, 比如 "{title} - S{season:02}E{episode:02}{ext}".
+// "{{" 和 "}}" 是花括号本身.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn new(template: &str) -> Result<Template, String> {
+        let err = |msg: &str| format!("invalid rename template {}: {}", template, msg);
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let var: String = chars.by_ref().take_while(|x| *x != '}').collect();
+                    let (name, width) = var.split_once(':').unwrap_or((&var, ""));
+                    let var = Var::new(name).ok_or(err(&format!("unknown variable {}", name)))?;
+                    let width = match width {
+                        "" => 0,
+                        width => width
+                            .parse()
+                            .map_err(|_| err(&format!("invalid width {}", width)))?,
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Var(var, width));
+                }
+                '}' => return Err(err("unmatched }")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if !parts.iter().any(|x| matches!(x, Part::Var(..))) {
+            return Err(err("no variables"));
+        }
+        Ok(Template {
+            template: template.to_string(),
+            parts,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    // 有变量识别不到时返回 None, 这个文件不重命名.
+    pub fn render(&self, fields: &Fields) -> Option<String> {
+        let mut name = String::new();
+        for part in &self.parts {
+            let (var, width) = match part {
+                Part::Text(text) => {
+                    name.push_str(text);
+                    continue;
+                }
+                Part::Var(var, width) => (var, *width),
+            };
+            match var {
+                Var::Title => name.push_str(fields.title.as_deref()?),
+                Var::Anime => name.push_str(&fields.anime),
+                Var::Season => name.push_str(&format!("{:0width$}", fields.season?)),
+                Var::Episode => name.push_str(&format!("{:0width$}", fields.episode?)),
+                Var::Ext => name.push_str(&fields.ext),
+                Var::Name => name.push_str(&fields.name),
+            }
+        }
+        // 新文件名不能变成路径.
+        (!name.is_empty() && !name.contains('/')).then_some(name)
+    }
+}
+
+// 模板变量的值, 识别不到的为 None.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields {
+    pub title: Option<String>,
+    pub anime: String,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub ext: String,
+    pub name: String,
+}
+
+impl Fields {
+    pub fn from_file_name(file_name: &str, media: &MediaRules) -> Fields {
+        let ext = extension(file_name, media);
+        let name = &file_name[..file_name.len() - ext.len()];
+        let release = Release::parse(name);
+        Fields {
+            episode: release.episode(),
+            title: release.title,
+            season: release.season,
+            ext: ext.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+// 后缀, 不是视频时包括前面的语言标记, 比如 "AIR [01].sc.ass" 的 ".sc.ass".
+fn extension<'a>(file_name: &'a str, media: &MediaRules) -> &'a str {
+    let ext = match media.extension(file_name) {
+        Some(ext) => ext,
+        None => file_name.rfind('.').map_or("", |i| &file_name[i..]),
+    };
+    if media.is_video(file_name) {
+        return ext;
+    }
+    let stem = &file_name[..file_name.len() - ext.len()];
+    match stem.rfind('.') {
+        Some(i)
+            if (2..=7).contains(&(stem.len() - i - 1))
+                && stem[i + 1..]
+                    .chars()
+                    .all(|x| x.is_ascii_alphabetic() || x == '-') =>
+        {
+            &file_name[i..]
+        }
+        _ => ext,
+    }
+}
+
+// 记录的一次重命名, 路径相对于动漫文件夹组合, 不在组合下时是绝对路径.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+// 计算每个文件的新文件名, 只改文件名, 不移动文件夹.
+// 无法生成或者没有变化的文件跳过, 新文件名重复时只保留第一个.
+pub fn plan(
+    files: &[PathBuf],
+    template: &Template,
+    media: &MediaRules,
+    anime: &str,
+    season: u32,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut targets = HashSet::new();
+    let mut pairs = Vec::new();
+    for file in files {
+        let Some(file_name) = file.file_name().and_then(|x| x.to_str()) else {
+            continue;
+        };
+        let mut fields = Fields::from_file_name(file_name, media);
+        fields.anime = anime.to_string();
+        fields.season = fields.season.or(Some(season));
+        let Some(new_name) = template.render(&fields) else {
+            continue;
+        };
+        let target = file.with_file_name(new_name);
+        if &target != file && targets.insert(target.clone()) {
+            pairs.push((file.clone(), target));
+        }
+    }
+    pairs
+}
+
+// 执行重命名, 返回成功的部分.
+// 目标已存在时跳过, 除非是 replaceable 中的上一次重命名的结果.
+pub fn rename_all(
+    pairs: &[(PathBuf, PathBuf)],
+    replaceable: &[PathBuf],
+) -> (Vec<(PathBuf, PathBuf)>, Vec<String>) {
+    let mut done = Vec::new();
+    let mut errors = Vec::new();
+    for (from, to) in pairs {
+        if to.exists() && !replaceable.contains(to) {
+            errors.push(format!(
+                "{}: {} already exists",
+                from.display(),
+                to.display()
+            ));
+            continue;
+        }
+        match fs::rename(from, to) {
+            Ok(_) => done.push((from.clone(), to.clone())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => errors.push(format!("{}: {}", from.display(), e)),
+        }
+    }
+    (done, errors)
+}
+
+// 路径和记录之间的转换, 记录相对于 base.
+impl Rename {
+    pub fn new(base: &Path, from: &Path, to: &Path) -> Rename {
+        let relative = |x: &Path| {
+            x.strip_prefix(base)
+                .unwrap_or(x)
+                .to_string_lossy()
+                .to_string()
+        };
+        Rename {
+            from: relative(from),
+            to: relative(to),
+        }
+    }
+
+    pub fn paths(&self, base: &Path) -> (PathBuf, PathBuf) {
+        (base.join(&self.from), base.join(&self.to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn render() {
+        let media = MediaRules::default();
+        let template = Template::new("{title} - S{season:02}E{episode:02}{ext}").unwrap();
+        let names: Vec<_> = [
+            "[VCB-Studio] AIR [01][Ma10p_1080p][x265_flac].mkv",
+            "[Nekomoe kissaten] Just Because! - 05v2 [1080p].sc.ass",
+            "[SweetSub] AIR S2 - 03 [WebRip][1080P].mp4",
+            "true tears S01E13.mkv",
+            "[VCB-Studio] Kimi no Na wa [Ma10p_1080p][x265_flac].mkv",
+        ]
+        .iter()
+        .map(|x| template.render(&Fields::from_file_name(x, &media)))
+        .collect();
+        assert_eq!(
+            names,
+            [
+                None,
+                None,
+                Some("AIR - S02E03.mp4".to_string()),
+                Some("true tears - S01E13.mkv".to_string()),
+                None,
+            ]
+        );
+
+        // 没有季度时使用 map 的季度.
+        let files = [
+            PathBuf::from("[VCB-Studio] AIR [01][Ma10p_1080p].mkv"),
+            PathBuf::from("[Nekomoe kissaten] Just Because! - 05v2 [1080p].sc.ass"),
+            PathBuf::from("AIR - S01E01.mkv"),
+        ];
+        let pairs = plan(&files, &template, &media, "AIR", 1);
+        let targets: Vec<_> = pairs.iter().map(|x| x.1.to_str().unwrap()).collect();
+        assert_eq!(
+            targets,
+            ["AIR - S01E01.mkv", "Just Because! - S01E05.sc.ass"]
+        );
+
+        let movie = Template::new("{anime} {{{name}}}{ext}").unwrap();
+        let fields = Fields {
+            anime: "君の名は。".to_string(),
+            name: "Kimi no Na wa".to_string(),
+            ext: ".mkv".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            movie.render(&fields).unwrap(),
+            "君の名は。 {Kimi no Na wa}.mkv"
+        );
+        assert!(Template::new("{title} - {ep}").is_err());
+        assert!(Template::new("plain.mkv").is_err());
+    }
+
+    #[test]
+    fn rename_all() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let dir = tep_dir.path();
+        fs::write(dir.join("a.mkv"), b"a").unwrap();
+        fs::write(dir.join("b.mkv"), b"b").unwrap();
+        fs::write(dir.join("c.mkv"), b"c").unwrap();
+        let pairs = [
+            (dir.join("a.mkv"), dir.join("A.mkv")),
+            (dir.join("b.mkv"), dir.join("c.mkv")),
+        ];
+        let (done, errors) = super::rename_all(&pairs, &[]);
+        assert_eq!(done, pairs[..1]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(fs::read(dir.join("c.mkv")).unwrap(), b"c");
+
+        let (done, _) = super::rename_all(&pairs[1..], &[dir.join("c.mkv")]);
+        assert_eq!(done.len(), 1);
+        let rename = Rename::new(dir, &pairs[0].0, &pairs[0].1);
+        assert_eq!(rename.to, "A.mkv");
+        assert_eq!(rename.paths(dir), pairs[0]);
+    }
+}
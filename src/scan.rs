@@ -0,0 +1,61 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+// 用最多 jobs 个线程处理 items, 结果和输入的顺序相同.
+// 每个线程依次领取下一个未处理的项, 慢的文件夹不会拖住其他线程.
+pub fn map<T: Send, R: Send>(items: Vec<T>, jobs: usize, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        return items.into_iter().map(f).collect();
+    }
+    let items: Vec<_> = items.into_iter().map(|x| Mutex::new(Some(x))).collect();
+    let results: Vec<_> = items.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let item = item.lock().unwrap().take().unwrap();
+                let result = f(item);
+                *results[i].lock().unwrap() = Some(result);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|x| x.into_inner().unwrap().unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread::sleep, time::Duration};
+
+    #[test]
+    fn map() {
+        let running = AtomicUsize::new(0);
+        let max = AtomicUsize::new(0);
+        let items: Vec<u64> = (0..20).collect();
+        let results = super::map(items, 4, |x| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max.fetch_max(now, Ordering::SeqCst);
+            // 前面的项更慢, 结果仍然按输入的顺序.
+            sleep(Duration::from_millis(20 - x));
+            running.fetch_sub(1, Ordering::SeqCst);
+            x * 2
+        });
+        assert_eq!(results, (0..20).map(|x| x * 2).collect::<Vec<_>>());
+        assert!(max.load(Ordering::SeqCst) <= 4);
+        assert_eq!(super::map(vec![1, 2], 0, |x| x + 1), [2, 3]);
+        assert!(super::map(Vec::<u8>::new(), 4, |x| x).is_empty());
+    }
+}
@@ -1,12 +1,16 @@
 use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::{linker::LinkMode, rename::Rename};
 
 // 文件类型.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 #[repr(u8)]
 pub enum FileType {
-    File,                         // 普通文件.
+    #[default]
+    File, // 普通文件.
     Dir,                          // 文件夹.
-    Other,                        // ".parts" 文件.
+    Other,                        // 被忽略的文件.
     Nesting(Vec<SourceAnimeMap>), // 文件夹里还是文件夹.
 }
 
@@ -17,12 +21,107 @@ impl FileType {
 }
 
 // 文件夹映射.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct SourceAnimeMap {
-    pub source: String,      // 源文件夹地址.
-    pub anime: String,       // 目标文件夹地址.
-    pub active: bool,        // 是否激活.
+    pub source: String, // 源文件夹地址.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub root: String, // 所属的文件夹组合名称.
+    pub anime: String,  // 目标文件夹地址.
+    pub active: bool,   // 是否激活.
     pub file_type: FileType, // 文件类型.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub link_modes: Vec<LinkMode>, // 单独指定的链接回退链, 为空时使用全局设置.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linked_by: Option<LinkMode>, // 实际使用的链接方式.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignored_by: Option<String>, // 匹配的忽略规则, 用于检查为什么被忽略.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileMap>, // 单独映射的文件, 没有列出的文件按文件夹映射链接.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sub_path: String, // 动漫文件夹下的子路径, 比如 "Specials".
+    #[serde(default, skip_serializing_if = "Layout::is_nested")]
+    pub layout: Layout, // 源文件夹的内容如何放到目标文件夹.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season: Option<u32>, // season 布局的季度, 为空时从源名称中识别.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub destination: String, // 匹配时解析出的链接后的地址, 相对于动漫文件夹组合.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>, // 单独指定的重命名模板, 为空字符串时不重命名.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub renamed: Vec<Rename>, // 链接后的重命名记录, 用于重复和撤销.
+}
+
+// 源文件夹在目标文件夹中的布局.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    #[default]
+    Nested, // 保持原样, 放到 <sub_path>/<源名称> 下.
+    Flatten, // 所有文件直接放到 <sub_path> 下.
+    Season,  // 源文件夹的内容放到 <sub_path>/Season NN 下.
+}
+
+impl Layout {
+    pub fn is_nested(&self) -> bool {
+        matches!(self, Layout::Nested)
+    }
+
+    // Jellyfin 识别的季度文件夹名称.
+    pub fn season_dir(season: u32) -> String {
+        format!("Season {:02}", season)
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Nested => write!(f, "nested"),
+            Layout::Flatten => write!(f, "flatten"),
+            Layout::Season => write!(f, "season"),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nested" => Ok(Layout::Nested),
+            "flatten" => Ok(Layout::Flatten),
+            "season" => Ok(Layout::Season),
+            _ => Err(format!("unknown layout: {}", s)),
+        }
+    }
+}
+
+// 文件夹映射下单独映射的文件.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FileMap {
+    pub source: String, // 相对于 map 源文件夹的路径.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub destination: String, // 相对于动漫文件夹组合的路径, 为空时和文件夹映射一致.
+    pub include: bool,  // 是否链接.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linked_by: Option<LinkMode>, // 实际使用的链接方式.
+}
+
+impl FileMap {
+    pub fn new(source: &str) -> FileMap {
+        FileMap {
+            source: source.to_string(),
+            destination: String::new(),
+            include: true,
+            linked_by: None,
+        }
+    }
+
+    // 不按文件夹映射链接的文件.
+    pub fn is_override(&self) -> bool {
+        !self.include || !self.destination.is_empty()
+    }
 }
 
 impl SourceAnimeMap {
@@ -34,62 +133,178 @@ impl SourceAnimeMap {
         &self.anime
     }
 
-    pub fn set_anime(&mut self, anime: Value<&String>) -> Result<(), &'static str> {
-        let f_index = |x: &mut Self, _: usize, v: &String| {
-            let FileType::Nesting(_) = &x.file_type else {
-                return;
-            };
-            if !x.anime.contains(v) {
+    // 嵌套的子 map, 不是嵌套时为空.
+    pub fn children(&self) -> &[SourceAnimeMap] {
+        match &self.file_type {
+            FileType::Nesting(maps) => maps,
+            _ => &[],
+        }
+    }
+
+    // 按源名称逐层查找子 map, sources 为空时是自身.
+    pub fn get(&self, sources: &[String]) -> Option<&SourceAnimeMap> {
+        match sources.split_first() {
+            None => Some(self),
+            Some((first, rest)) => self
+                .children()
+                .iter()
+                .find(|x| &x.source == first)?
+                .get(rest),
+        }
+    }
+
+    pub fn get_mut(&mut self, sources: &[String]) -> Option<&mut SourceAnimeMap> {
+        let Some((first, rest)) = sources.split_first() else {
+            return Some(self);
+        };
+        match &mut self.file_type {
+            FileType::Nesting(maps) => maps.iter_mut().find(|x| &x.source == first)?.get_mut(rest),
+            _ => None,
+        }
+    }
+
+    // 嵌套的父 map 的 anime 是所有子 map 的 anime 的合集.
+    pub fn set_anime(&mut self, sources: &[String], anime: &String) -> Result<(), String> {
+        let f_parent = |x: &mut Self, v: &String| {
+            if !x.anime.contains(v.as_str()) {
                 if !x.anime.is_empty() {
                     x.anime.push_str(", ")
                 }
                 x.anime.push_str(v);
             }
         };
-        self.set_value(anime, |x, f| x.anime = f.to_owned(), f_index)
+        self.set_value(sources, anime, |x, f| x.anime = f.to_owned(), f_parent)
     }
 
-    pub fn set_active(&mut self, active: Value<bool>) -> Result<(), &'static str> {
-        let f_index = |x: &mut Self, _: usize, _: bool| {
-            let FileType::Nesting(maps) = &x.file_type else {
-                return;
-            };
-            x.active = maps.iter().any(|x| x.active);
+    // 嵌套的父 map 在有子 map 激活时激活.
+    pub fn set_active(&mut self, sources: &[String], active: bool) -> Result<(), String> {
+        let f_parent = |x: &mut Self, _: bool| {
+            x.active = x.children().iter().any(|x| x.active);
         };
-        self.set_value(active, |x, f| x.active = f, f_index)
+        self.set_value(sources, active, |x, f| x.active = f, f_parent)
     }
 
-    // 给字段设置 value 的通用方法.
-    // 可以使用泛型来实现.
+    pub fn set_linked_by(&mut self, sources: &[String], mode: LinkMode) -> Result<(), String> {
+        self.set_value(sources, mode, |x, f| x.linked_by = Some(f), |_, _| {})
+    }
+
+    // 查找单独映射的文件, 不存在时添加.
+    pub fn file_mut(&mut self, source: &str) -> &mut FileMap {
+        let index = match self.files.iter().position(|x| x.source == source) {
+            Some(index) => index,
+            None => {
+                self.files.push(FileMap::new(source));
+                self.files.len() - 1
+            }
+        };
+        &mut self.files[index]
+    }
+
+    // 给 sources 处的 map 设置 value 的通用方法.
     // f_base 是字段的基础设置方法.
-    // f_index 是如果是嵌套字段, 需要额外的代码.
+    // f_parent 是路径上的每一层父 map 需要额外执行的代码, 从内向外执行.
     fn set_value<T: Copy>(
         &mut self,
-        value: Value<T>,
+        sources: &[String],
+        value: T,
         f_base: fn(&mut Self, T),
-        f_index: fn(&mut Self, usize, T),
-    ) -> Result<(), &'static str> {
-        match value {
-            Value::Base(x) => f_base(self, x),
-            Value::Index((i, x)) => match self.file_type {
-                FileType::Nesting(ref mut maps) => {
-                    maps[i].set_value(Value::Base(x), f_base, f_index)?;
-                    f_index(self, i, x);
-                }
-                _ => {
-                    if i == 0 {
-                        f_base(self, x);
-                    } else {
-                        return Err("set value error. file_type isn't nesting.");
-                    }
-                }
-            },
-        }
+        f_parent: fn(&mut Self, T),
+    ) -> Result<(), String> {
+        let Some((first, rest)) = sources.split_first() else {
+            f_base(self, value);
+            return Ok(());
+        };
+        let FileType::Nesting(maps) = &mut self.file_type else {
+            return Err(format!("set value error. {} isn't nesting.", self.source));
+        };
+        let child = maps
+            .iter_mut()
+            .find(|x| &x.source == first)
+            .ok_or(format!("set value error. {} not found.", first))?;
+        child.set_value(rest, value, f_base, f_parent)?;
+        f_parent(self, value);
         Ok(())
     }
 }
 
-pub enum Value<T> {
-    Base(T),
-    Index((usize, T)),
+// map 的地址: 所属的文件夹组合和从顶层开始每一层的源名称.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MapPath {
+    pub root: String,
+    pub sources: Vec<String>,
+}
+
+impl MapPath {
+    // source 是 "Collection/Series/Season" 形式的路径.
+    pub fn new(root: &str, source: &str) -> MapPath {
+        MapPath {
+            root: root.to_string(),
+            sources: source.split('/').map(String::from).collect(),
+        }
+    }
+
+    pub fn child(&self, source: &str) -> MapPath {
+        let mut path = self.clone();
+        path.sources.push(source.to_string());
+        path
+    }
+}
+
+// 输出为 "Collection/Series/Season".
+impl fmt::Display for MapPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.sources.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(source: &str, file_type: FileType) -> SourceAnimeMap {
+        SourceAnimeMap {
+            source: source.to_string(),
+            active: true,
+            file_type,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!("season".parse::<Layout>().unwrap(), Layout::Season);
+        assert!("flat".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn set_value() {
+        let mut collection = map(
+            "Collection",
+            FileType::Nesting(vec![map(
+                "Series",
+                FileType::Nesting(vec![
+                    map("Season 1", FileType::Dir),
+                    map("Season 2", FileType::Dir),
+                ]),
+            )]),
+        );
+        let path = MapPath::new("", "Collection/Series/Season 2");
+        assert_eq!(path.to_string(), "Collection/Series/Season 2");
+        let sources = &path.sources[1..];
+
+        collection.set_anime(sources, &"AIR".to_string()).unwrap();
+        collection.set_active(sources, false).unwrap();
+        assert_eq!(collection.get(sources).unwrap().anime, "AIR");
+        assert_eq!(collection.anime, "AIR");
+        assert!(collection.active);
+
+        let season_1 = &MapPath::new("", "Collection/Series/Season 1").sources[1..];
+        collection.set_active(season_1, false).unwrap();
+        assert!(!collection.get(&sources[..1]).unwrap().active);
+        assert!(!collection.active);
+
+        let not_found = &MapPath::new("", "Collection/Series/Season 3").sources[1..];
+        assert!(collection.set_active(not_found, false).is_err());
+        assert!(collection.get_mut(not_found).is_none());
+    }
 }
@@ -0,0 +1,135 @@
+use chrono::Local;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// 备份文件夹, 和 map 文件在同一个文件夹下.
+const BACKUP_DIR: &str = "backups";
+
+// 先写临时文件再重命名, 中途失败时原文件保持不变.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let dir = parent_dir(path);
+    fs::create_dir_all(dir)?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = dir.join(name);
+
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    if let Err(e) = result.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_dir(dir)
+}
+
+// 把现有文件复制到备份文件夹, 只保留最新的 keep 份.
+// 文件不存在或内容没有变化时不备份.
+pub fn backup(path: &Path, content: &[u8], keep: usize) -> io::Result<Option<PathBuf>> {
+    let old = match fs::read(path) {
+        Ok(old) => old,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if keep == 0 || old == content {
+        return Ok(None);
+    }
+    let dir = backup_dir(path);
+    fs::create_dir_all(&dir)?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", Local::now().format("%Y%m%d-%H%M%S-%3f")));
+    let backup = dir.join(name);
+    write_atomic(&backup, &old)?;
+
+    for old in list_backups(path)?.into_iter().skip(keep) {
+        fs::remove_file(old)?;
+    }
+    Ok(Some(backup))
+}
+
+// 所有备份, 最新的在前.
+pub fn list_backups(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut prefix = path.file_name().unwrap_or_default().to_os_string();
+    prefix.push(".");
+    let prefix = prefix.to_string_lossy().to_string();
+    let entries = match fs::read_dir(backup_dir(path)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut backups: Vec<_> = entries
+        .flatten()
+        .filter(|x| {
+            let name = x.file_name().to_string_lossy().to_string();
+            name.starts_with(&prefix) && !name.ends_with(".tmp")
+        })
+        .map(|x| x.path())
+        .collect();
+    // 时间戳定长, 按名称排序就是按时间排序.
+    backups.sort();
+    backups.reverse();
+    Ok(backups)
+}
+
+// 用备份替换现有文件, 替换前先备份现有文件, 所以可以撤销.
+pub fn restore(path: &Path, backup: &Path, keep: usize) -> io::Result<()> {
+    let content = fs::read(backup)?;
+    self::backup(path, &content, keep)?;
+    write_atomic(path, &content)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn backup_dir(path: &Path) -> PathBuf {
+    parent_dir(path).join(BACKUP_DIR)
+}
+
+// 重命名只有在文件夹同步后才能保证落盘.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::*;
+
+    #[test]
+    fn backup_and_restore() {
+        let tep_dir = tempdir_in("./").unwrap();
+        let path = tep_dir.path().join("data.yaml");
+        assert_eq!(backup(&path, b"1", 2).unwrap(), None);
+        write_atomic(&path, b"1").unwrap();
+        assert_eq!(backup(&path, b"1", 2).unwrap(), None);
+
+        for content in ["2", "3", "4"] {
+            backup(&path, content.as_bytes(), 2).unwrap().unwrap();
+            write_atomic(&path, content.as_bytes()).unwrap();
+            // 时间戳精确到毫秒.
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let backups = list_backups(&path).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(fs::read(&backups[0]).unwrap(), b"3");
+        assert_eq!(fs::read(&backups[1]).unwrap(), b"2");
+
+        restore(&path, &backups[1], 2).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"2");
+        assert_eq!(fs::read(&list_backups(&path).unwrap()[0]).unwrap(), b"4");
+    }
+}
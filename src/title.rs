@@ -0,0 +1,142 @@
+use unicode_normalization::UnicodeNormalization;
+
+use crate::release::Release;
+
+// 归一化名称: NFKC 统一全角半角, 转小写, 标点和分隔符变成空格.
+pub fn normalize(name: &str) -> String {
+    name.nfkc()
+        .flat_map(char::to_lowercase)
+        .map(|x| if x.is_alphanumeric() { x } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// 源文件或文件夹名中的标题, 去掉字幕组, 方括号中的标签, 分辨率和集数.
+// 先统一全角半角, 全角的季度和集数也能识别.
+pub fn source_title(name: &str) -> String {
+    let name: String = name.nfkc().collect();
+    let title = Release::parse(&name)
+        .title
+        .unwrap_or_else(|| strip_brackets(&name).concat());
+    normalize(&title)
+}
+
+// 动漫文件夹的名称, 比如 "AIR [青空]" 的 "air" 和 "青空".
+pub fn aliases(anime: &str) -> Vec<String> {
+    let mut aliases: Vec<String> = Vec::new();
+    for alias in strip_brackets(anime).into_iter().chain(brackets(anime)) {
+        let alias = normalize(alias);
+        if !alias.is_empty() && !aliases.contains(&alias) {
+            aliases.push(alias);
+        }
+    }
+    aliases
+}
+
+// 两个归一化名称的相似度, 0 到 1.
+// 字符二元组的 Dice 系数和编辑距离取较大值, 分别适合词序变化和少量错字.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    strsim::sorensen_dice(a, b).max(strsim::normalized_levenshtein(a, b))
+}
+
+// 源标题和动漫文件夹的相似度, 返回最相似的名称和分数.
+// title 是 source_title 的结果, 和多个动漫比较时只解析一次.
+pub fn score(title: &str, anime: &str) -> Option<(String, f64)> {
+    aliases(anime)
+        .into_iter()
+        .map(|x| {
+            let score = similarity(title, &x);
+            (x, score)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+const OPEN: [char; 3] = ['[', '【', '('];
+const CLOSE: [char; 3] = [']', '】', ')'];
+
+// 方括号外的部分.
+fn strip_brackets(name: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in name.char_indices() {
+        if OPEN.contains(&c) {
+            if depth == 0 {
+                parts.push(&name[start..i]);
+            }
+            depth += 1;
+        } else if CLOSE.contains(&c) && depth > 0 {
+            depth -= 1;
+            start = i + c.len_utf8();
+        }
+    }
+    if depth == 0 {
+        parts.push(&name[start..]);
+    }
+    parts
+}
+
+// 最外层方括号中的部分.
+fn brackets(name: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in name.char_indices() {
+        if OPEN.contains(&c) {
+            if depth == 0 {
+                start = i + c.len_utf8();
+            }
+            depth += 1;
+        } else if CLOSE.contains(&c) && depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                parts.push(&name[start..i]);
+            }
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(super::normalize("ＡＩＲ　Ｓ２"), "air s2");
+        assert_eq!(super::normalize("Just_Because!"), "just because");
+        assert_eq!(super::normalize("君の名は。"), "君の名は");
+        assert_eq!(source_title("[VCB-Studio] AIR [Ma10p_1080p]"), "air");
+        assert_eq!(source_title("[SweetSub] AIR S2 - 03 [1080P].mp4"), "air");
+        assert_eq!(aliases("AIR [青空]"), ["air", "青空"]);
+        assert_eq!(
+            aliases("君の名は。 【你的名字。】"),
+            ["君の名は", "你的名字"]
+        );
+    }
+
+    #[test]
+    fn score() {
+        let title_score = |source: &str, anime: &str| super::score(&source_title(source), anime);
+        let (alias, score) = title_score("[Sakurato] 青空 [01-13][1080p]", "AIR [青空]").unwrap();
+        assert_eq!(alias, "青空");
+        assert_eq!(score, 1.0);
+        let (_, score) =
+            title_score("[VCB-Studio] Ａｉｒ Ｓ2 [Ma10p_1080p]", "AIR [青空]").unwrap();
+        assert_eq!(score, 1.0);
+        let (_, score) = title_score(
+            "[Nekomoe] Just Becuase! [1080p]",
+            "Just Because! [恋爱随意]",
+        )
+        .unwrap();
+        assert!(score > 0.8, "{}", score);
+        let (_, score) = title_score("[VCB-Studio] Air Gear [1080p]", "AIR [青空]").unwrap();
+        assert!(score < 0.8, "{}", score);
+        assert_eq!(title_score("AIR", "[]"), None);
+    }
+}